@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use secret_pics::{Chunk, ChunkReader, ChunkType, ChunkWriter, Crc32};
+use std::str::FromStr;
+
+// IDAT chunks in large photos are commonly tens of megabytes
+const SIZES: [usize; 3] = [64 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+
+fn idat_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 31 + i / 7) as u8).collect()
+}
+
+fn bench_crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc32");
+    for size in SIZES {
+        let data = idat_data(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| {
+                let mut hasher = Crc32::new();
+                hasher.update(black_box(data));
+                hasher.finish()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_read_idat(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_idat_chunk");
+    for size in SIZES {
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for (name, data) in [("IDAT", idat_data(size)), ("IEND", Vec::new())] {
+            let chunk_type = ChunkType::from_str(name).unwrap();
+            writer.write_chunk(&Chunk::new(chunk_type, data)).unwrap();
+        }
+        let png = writer.finish().unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &png, |b, png| {
+            b.iter(|| {
+                ChunkReader::new(black_box(png.as_slice()))
+                    .unwrap()
+                    .map(|chunk| chunk.unwrap().length())
+                    .sum::<u32>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_crc, bench_read_idat);
+criterion_main!(benches);
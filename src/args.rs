@@ -1,4 +1,6 @@
-use clap::{Args, Subcommand};
+use clap::builder::NonEmptyStringValueParser;
+use clap::{ArgGroup, Args, Subcommand, ValueEnum};
+use secret_pics::{Cipher, Compression, Placement};
 use std::path::PathBuf;
 
 #[derive(Subcommand)]
@@ -9,30 +11,310 @@ pub enum PngMeArgs {
     Decode(DecodeArgs),
     /// Remove message
     Remove(RemoveArgs),
-    /// Print all the messages
+    /// Change a message where it is in the file
+    Update(UpdateArgs),
+    /// Print every chunk of the file
     Print(PrintArgs),
+    /// Print the image size and a summary of its chunks
+    Info(InfoArgs),
+    /// Generate a key pair for encrypting messages to recipients
+    Keygen(KeygenArgs),
+    /// Check the signature of a message
+    Verify(VerifyArgs),
+}
+
+/// How results and errors are written
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    /// one json document on stdout, see the README for the schema
+    Json,
 }
 #[derive(Args)]
+#[command(group(ArgGroup::new("encryption").args(["encrypt", "recipient"])))]
 pub struct EncodeArgs {
+    /// The png file, - to read it from stdin
     pub file_path: PathBuf,
     pub chunk_type: String,
-    pub message: String,
+    // clap fills this before output_file, so with --file or --stdin it may
+    // hold the output file instead, encode sorts that out
+    #[arg(required_unless_present_any = ["file", "stdin"])]
+    pub message: Option<String>,
+    // deprecated in favour of -o, still accepted so older scripts run
+    #[arg(hide = true, conflicts_with_all = ["output", "backup"])]
     pub output_file: Option<PathBuf>,
+    /// Embed the raw bytes of this file instead of a message
+    #[arg(long, value_name = "PATH", conflicts_with = "stdin")]
+    pub file: Option<PathBuf>,
+    /// Embed the raw bytes read from stdin instead of a message
+    #[arg(long)]
+    pub stdin: bool,
+    /// Mime type stored with the message, text/plain for a message argument
+    #[arg(long, value_name = "TYPE")]
+    pub content_type: Option<String>,
+    /// Compress the message before storing it, with zlib unless another
+    /// algorithm is given
+    #[arg(long, value_enum, value_name = "ALGORITHM", num_args = 0..=1, default_missing_value = "zlib")]
+    pub compress: Option<CompressionArg>,
+    /// Encrypt the message with a passphrase, read from SECRET_PICS_PASSPHRASE
+    /// or prompted for
+    #[arg(long)]
+    pub encrypt: bool,
+    /// Encrypt the message to this public key, or every public key in this
+    /// file; repeat to add more recipients
+    #[arg(long, value_name = "KEY")]
+    pub recipient: Vec<String>,
+    /// Sign the message with the signing key in this file
+    #[arg(long, value_name = "PATH")]
+    pub sign: Option<PathBuf>,
+    /// Make the signature cover the image's IHDR and IDAT data too
+    #[arg(long, requires = "sign")]
+    pub bind_image: bool,
+    /// Cipher to encrypt with
+    #[arg(long, value_enum, default_value_t = CipherArg::Chacha20Poly1305, requires = "encryption")]
+    pub cipher: CipherArg,
+    /// Replace the messages already hidden under this chunk type instead of
+    /// adding another
+    #[arg(long)]
+    pub replace: bool,
+    /// Split the message across chunks holding at most this many bytes each
+    #[arg(long, value_name = "BYTES")]
+    pub split: Option<usize>,
+    /// Where to put the message chunk
+    #[arg(long, value_enum, default_value_t = PlacementArg::BeforeIend)]
+    pub placement: PlacementArg,
+    /// Use the chunk type even if it is critical, which corrupts the image,
+    /// and without warning when it is public
+    #[arg(long)]
+    pub force: bool,
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub save: OutputArgs,
+}
+
+/// How the png is read, shared by the commands that load one
+#[derive(Args)]
+pub struct InputArgs {
+    /// Recompute damaged chunk crcs instead of refusing the file
+    #[arg(long)]
+    pub repair: bool,
+}
+
+/// Where a command that changes the png writes the result
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Write the result to this new file instead of over the original, - for
+    /// stdout, the default when the png comes from stdin
+    #[arg(short, long, value_name = "PATH", conflicts_with = "backup")]
+    pub output: Option<PathBuf>,
+    /// Keep the original file, under its name with this suffix added
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ".bak",
+        value_parser = NonEmptyStringValueParser::new()
+    )]
+    pub backup: Option<String>,
+}
+
+/// How a command that only reads the png gets at its bytes
+#[derive(Args)]
+pub struct MapArgs {
+    /// Map the file into memory instead of reading it all first, so a huge
+    /// image costs no memory up front; the file must not change meanwhile
+    #[arg(long)]
+    pub mmap: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PlacementArg {
+    BeforeIend,
+    BeforeIdat,
+    AfterIhdr,
+}
+
+impl From<PlacementArg> for Placement {
+    fn from(placement: PlacementArg) -> Self {
+        match placement {
+            PlacementArg::BeforeIend => Placement::BeforeIend,
+            PlacementArg::BeforeIdat => Placement::BeforeIdat,
+            PlacementArg::AfterIhdr => Placement::AfterIhdr,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CipherArg {
+    Chacha20Poly1305,
+    #[value(name = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+impl From<CipherArg> for Cipher {
+    fn from(cipher: CipherArg) -> Self {
+        match cipher {
+            CipherArg::Chacha20Poly1305 => Cipher::ChaCha20Poly1305,
+            CipherArg::Aes256Gcm => Cipher::Aes256Gcm,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CompressionArg {
+    Zlib,
+    /// only in builds with the zstd feature
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl From<CompressionArg> for Compression {
+    fn from(compression: CompressionArg) -> Self {
+        match compression {
+            CompressionArg::Zlib => Compression::Zlib,
+            #[cfg(feature = "zstd")]
+            CompressionArg::Zstd => Compression::Zstd,
+        }
+    }
 }
 
 #[derive(Args)]
 pub struct DecodeArgs {
+    /// The png file, - to read it from stdin
     pub file_path: PathBuf,
     pub chunk_type: String,
+    /// Decode every message of this chunk type, not just the first
+    #[arg(long, conflicts_with_all = ["index", "out", "stdout"])]
+    pub all: bool,
+    /// Decode the message at this index among those of the chunk type,
+    /// counting from 0
+    #[arg(long, value_name = "N")]
+    pub index: Option<usize>,
+    /// Write the raw message bytes to this file instead of printing them, -
+    /// for stdout
+    #[arg(long, value_name = "PATH", conflicts_with = "stdout")]
+    pub out: Option<PathBuf>,
+    /// Write the raw message bytes to stdout instead of printing them
+    #[arg(long)]
+    pub stdout: bool,
+    /// Decrypt with the identities in this file; repeat to try more files
+    #[arg(short, long, value_name = "PATH")]
+    pub identity: Vec<PathBuf>,
+    /// Refuse a compressed message that expands to more than this many bytes
+    #[arg(long, value_name = "BYTES", default_value_t = Compression::DEFAULT_LIMIT)]
+    pub max_size: usize,
+    /// Decode a signed message even when its signature does not check out
+    #[arg(long)]
+    pub ignore_signature: bool,
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub map: MapArgs,
 }
 
 #[derive(Args)]
 pub struct RemoveArgs {
+    /// The png file, - to read it from stdin
+    pub file_path: PathBuf,
+    pub chunk_type: String,
+    /// Remove every message of this chunk type, not just the first
+    #[arg(long, conflicts_with = "index")]
+    pub all: bool,
+    /// Remove the message at this index among those of the chunk type,
+    /// counting from 0
+    #[arg(long, value_name = "N")]
+    pub index: Option<usize>,
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub save: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct UpdateArgs {
+    /// The png file, - to read it from stdin
     pub file_path: PathBuf,
     pub chunk_type: String,
+    /// The new message
+    #[arg(required_unless_present = "edit", conflicts_with = "edit")]
+    pub message: Option<String>,
+    /// Edit the current message in $VISUAL or $EDITOR instead
+    #[arg(long)]
+    pub edit: bool,
+    /// Update the message at this index among those of the chunk type,
+    /// counting from 0
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub index: usize,
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub save: OutputArgs,
 }
 
 #[derive(Args)]
 pub struct PrintArgs {
+    /// The png file, - to read it from stdin
+    pub file_path: PathBuf,
+    /// Only critical chunks
+    #[arg(long, conflicts_with = "ancillary")]
+    pub critical: bool,
+    /// Only ancillary chunks
+    #[arg(long)]
+    pub ancillary: bool,
+    /// Only public chunks
+    #[arg(long, conflicts_with = "private")]
+    pub public: bool,
+    /// Only private chunks
+    #[arg(long)]
+    pub private: bool,
+    /// Only chunks whose type matches this pattern, `*` and `?` are wildcards
+    #[arg(long = "type", value_name = "GLOB")]
+    pub chunk_type: Option<String>,
+    // print reports the crc of every chunk and never refuses a damaged
+    // file, the flag is only accepted so older scripts keep working
+    #[arg(long, hide = true)]
+    pub repair: bool,
+    #[command(flatten)]
+    pub map: MapArgs,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    /// The png file, - to read it from stdin
+    pub file_path: PathBuf,
+    #[command(flatten)]
+    pub map: MapArgs,
+}
+
+#[derive(Args)]
+pub struct KeygenArgs {
+    /// Where to write the identity; the public key goes next to it in PATH.pub
+    #[arg(value_name = "PATH")]
+    pub identity_path: PathBuf,
+    /// Generate an ed25519 signing key for --sign instead
+    #[arg(long)]
+    pub signing: bool,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// The png file, - to read it from stdin
     pub file_path: PathBuf,
+    pub chunk_type: String,
+    /// Check every signed message of this chunk type, not just the first
+    /// message
+    #[arg(long, conflicts_with = "index")]
+    pub all: bool,
+    /// Check the message at this index among those of the chunk type,
+    /// counting from 0
+    #[arg(long, value_name = "N")]
+    pub index: Option<usize>,
+    /// Also require the message to be signed by this verifying key, or one of
+    /// the keys in this file
+    #[arg(long, value_name = "KEY")]
+    pub signer: Option<String>,
+    #[command(flatten)]
+    pub map: MapArgs,
 }
@@ -0,0 +1,246 @@
+use crate::chunk::{serialize_data, Chunk};
+use crate::chunk_type::ChunkType;
+use crate::parse::{short_chunk, Parser};
+use crate::png::{CrcRepair, Png};
+use crate::{Error, Result};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::convert::TryFrom;
+use std::str;
+use std::str::FromStr;
+
+/// A chunk borrowed from the buffer it was parsed from, its data is never
+/// copied. Use [`ChunkRef::to_chunk`] to get an owned [`Chunk`] to edit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+    offset: usize,
+}
+
+impl<'a> ChunkRef<'a> {
+    pub fn chunk_type(&self) -> ChunkType {
+        self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Byte offset of the chunk in the buffer it was parsed from
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn data_as_str(&self) -> Result<&'a str> {
+        Ok(str::from_utf8(self.data)?)
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        // the parser has already checked the length and crc
+        Chunk::from_parts_unchecked(self.length(), self.chunk_type, self.data.to_vec(), self.crc)
+    }
+}
+
+/// Serialized like [`Chunk`] with its `offset` added
+impl Serialize for ChunkRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("offset", &self.offset)?;
+        map.serialize_entry("type", &self.chunk_type)?;
+        map.serialize_entry("length", &self.length())?;
+        map.serialize_entry("crc", &self.crc)?;
+        map.serialize_entry("properties", &self.chunk_type.properties())?;
+        serialize_data(&mut map, self.data)?;
+        map.end()
+    }
+}
+
+/// A png parsed in place from a byte buffer, such as a whole file read into
+/// memory or a memory map. It runs the same checks as [`Png`] but keeps
+/// only borrowed [`ChunkRef`]s, so listing or reading chunks of a large
+/// image never copies its pixel data.
+#[derive(Debug)]
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+}
+
+impl<'a> PngRef<'a> {
+    /// Like [`Png::from_bytes_repairing`], chunks with a bad crc get the
+    /// computed crc instead of failing the parse
+    pub fn parse_repairing(bytes: &'a [u8]) -> Result<(PngRef<'a>, Vec<CrcRepair>)> {
+        PngRef::parse(bytes, true)
+    }
+
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        let chunk_type = ChunkType::from_str(chunk_type).ok()?;
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type == chunk_type)
+    }
+
+    /// Every chunk of the given type, in file order
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&ChunkRef<'a>> {
+        let Ok(chunk_type) = ChunkType::from_str(chunk_type) else {
+            return Vec::new();
+        };
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == chunk_type)
+            .collect()
+    }
+
+    /// Copies every chunk into an owned, editable [`Png`]
+    pub fn to_png(&self) -> Png {
+        Png::from_chunks(self.chunks.iter().map(ChunkRef::to_chunk).collect())
+    }
+
+    fn parse(bytes: &'a [u8], repair: bool) -> Result<(PngRef<'a>, Vec<CrcRepair>)> {
+        let mut parser = Parser::new(&bytes[..bytes.len().min(8)])?;
+        if repair {
+            parser = parser.repairing();
+        }
+        let mut chunks = Vec::new();
+        loop {
+            let offset = parser.offset;
+            let rest = bytes.get(offset..).unwrap_or_default();
+            let Some(header) = parser.header(&rest[..rest.len().min(8)])? else {
+                break;
+            };
+            let rest = &rest[8..];
+            let length = header.length as usize;
+            if rest.len() < length + 4 {
+                return Err(short_chunk(header, rest.len()));
+            }
+            let (data, stored) = rest.split_at(length);
+            let stored = u32::from_be_bytes(stored[..4].try_into().unwrap());
+            let (chunk_type, crc) = parser.check_chunk(header, data, stored)?;
+            chunks.push(ChunkRef {
+                chunk_type,
+                data,
+                crc,
+                offset,
+            });
+        }
+        Ok((PngRef { chunks }, parser.repairs))
+    }
+}
+
+impl Serialize for PngRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("chunks", &self.chunks)?;
+        map.end()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<PngRef<'a>> {
+        let (png, _) = PngRef::parse(bytes, false)?;
+        Ok(png)
+    }
+}
+
+impl<'a> From<ChunkRef<'a>> for Chunk {
+    fn from(chunk: ChunkRef<'a>) -> Chunk {
+        chunk.to_chunk()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::ChunkWriter;
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for (chunk_type, data) in [("IHDR", "header"), ("ruSt", "message"), ("IEND", "")] {
+            let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+            writer
+                .write_chunk(&Chunk::new(chunk_type, data.as_bytes().to_vec()))
+                .unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_chunks_borrow_from_buffer() {
+        let bytes = testing_bytes();
+        let png = PngRef::try_from(bytes.as_slice()).unwrap();
+        let chunk = png.chunk_by_type("ruSt").unwrap();
+        assert_eq!(chunk.data_as_str().unwrap(), "message");
+        assert_eq!(chunk.offset(), 26);
+        assert!(std::ptr::eq(chunk.data().as_ptr(), bytes[34..].as_ptr()));
+    }
+
+    #[test]
+    fn test_matches_owned_png() {
+        let bytes = testing_bytes();
+        let borrowed = PngRef::try_from(bytes.as_slice()).unwrap();
+        let owned = Png::try_from(bytes.as_slice()).unwrap();
+        let converted: Vec<Chunk> = borrowed.chunks().iter().map(ChunkRef::to_chunk).collect();
+        assert_eq!(converted, owned.chunks());
+        assert_eq!(borrowed.to_png().as_bytes(), bytes);
+        assert_eq!(borrowed.chunks_by_type("ruSt").len(), 1);
+        assert_eq!(owned.chunks_by_type("ruSt").len(), 1);
+    }
+
+    #[test]
+    fn test_same_errors_as_owned_png() {
+        let mut bytes = testing_bytes();
+        bytes[25] ^= 0xff;
+        assert!(matches!(
+            PngRef::try_from(bytes.as_slice()),
+            Err(Error::CrcMismatch { offset: 8, .. })
+        ));
+        let (_, repairs) = PngRef::parse_repairing(&bytes).unwrap();
+        assert_eq!(repairs.len(), 1);
+
+        let bytes = testing_bytes();
+        assert!(matches!(
+            PngRef::try_from(&bytes[..bytes.len() - 12]),
+            Err(Error::MissingIend { .. })
+        ));
+    }
+
+    #[test]
+    fn test_every_truncation_fails_like_the_stream_reader() {
+        let bytes = testing_bytes();
+        for end in 0..bytes.len() {
+            let borrowed = PngRef::try_from(&bytes[..end]).unwrap_err();
+            let owned = Png::try_from(&bytes[..end]).unwrap_err();
+            assert_eq!(borrowed.to_string(), owned.to_string());
+        }
+    }
+
+    #[test]
+    fn test_serialize_matches_owned_png() {
+        let bytes = testing_bytes();
+        let borrowed = serde_json::to_value(PngRef::try_from(bytes.as_slice()).unwrap()).unwrap();
+        let mut owned = serde_json::to_value(Png::try_from(bytes.as_slice()).unwrap()).unwrap();
+        assert_eq!(borrowed["chunks"][1]["offset"], 26);
+        for (i, chunk) in owned["chunks"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+            .enumerate()
+        {
+            chunk["offset"] = borrowed["chunks"][i]["offset"].clone();
+        }
+        assert_eq!(borrowed, owned);
+    }
+}
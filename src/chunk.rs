@@ -2,55 +2,82 @@
 // be variable leangth and finally crc
 
 use crate::chunk_type::ChunkType;
+pub use crate::crc::crc;
+use crate::crc::Crc32;
 use crate::{Error, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde::ser::{Serialize, SerializeMap, Serializer};
 use std::convert::TryFrom;
 use std::fmt;
-use std::io::{BufReader, Read};
 use std::str;
-pub fn crc(buf: &[u8]) -> u32 {
-    const CRC_TABLE_SIZE: usize = 256;
-    let crc_table: [u32; CRC_TABLE_SIZE] = {
-        let mut table = [0; CRC_TABLE_SIZE];
-        for n in 0..CRC_TABLE_SIZE {
-            let mut c = n as u32;
-            for _ in 0..8 {
-                if c & 1 == 1 {
-                    c = 0xedb88320 ^ (c >> 1);
-                } else {
-                    c >>= 1;
-                }
-            }
-            table[n] = c;
-        }
-        table
-    };
-    let mut c = 0xffffffff;
-    for &b in buf {
-        c = crc_table[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
-    }
-    c ^ 0xffffffff
-}
+/// A chunk whose length and crc always agree with its type and data, unless
+/// it was deliberately built with [`Chunk::from_parts_unchecked`]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Chunk {
-    pub length: u32,
-    pub chunk_type: ChunkType,
-    pub data: Vec<u8>,
-    pub crc: u32,
+    length: u32,
+    chunk_type: ChunkType,
+    data: Vec<u8>,
+    crc: u32,
 }
 impl Chunk {
+    /// the png spec limits chunk lengths to 2^31 - 1 bytes
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
+
+    /// Builds a chunk, computing its length and crc.
+    ///
+    /// # Panics
+    ///
+    /// If `data` is longer than [`Chunk::MAX_LENGTH`], see [`Chunk::try_new`]
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let concated = [chunk_type.bytes().as_ref(), data.as_slice()].concat();
-        let checksum = crc(&concated);
+        match Chunk::try_new(chunk_type, data) {
+            Ok(chunk) => chunk,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Builds a chunk, computing its length and crc, or fails if `data` is
+    /// longer than [`Chunk::MAX_LENGTH`]
+    pub fn try_new(chunk_type: ChunkType, data: Vec<u8>) -> Result<Chunk> {
+        let length = checked_length(&data)?;
+        Ok(Chunk {
+            length,
+            chunk_type,
+            crc: checksum(chunk_type, &data),
+            data,
+        })
+    }
+
+    /// Builds a chunk exactly as given without checking that `length` and
+    /// `crc` match the data. Meant for tools that need to write deliberately
+    /// broken chunks; everything else should use [`Chunk::new`].
+    pub fn from_parts_unchecked(
+        length: u32,
+        chunk_type: ChunkType,
+        data: Vec<u8>,
+        crc: u32,
+    ) -> Chunk {
         Chunk {
-            length: data.len() as u32,
+            length,
             chunk_type,
             data,
-            crc: checksum,
+            crc,
         }
     }
 
-    pub fn data(&self) -> Vec<u8> {
-        self.data.clone()
+    /// Replaces the data, recomputing the length and crc to match
+    pub fn set_data(&mut self, data: Vec<u8>) -> Result<()> {
+        self.length = checked_length(&data)?;
+        self.crc = checksum(self.chunk_type, &data);
+        self.data = data;
+        Ok(())
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
     }
 
     pub fn chunk_type(&self) -> ChunkType {
@@ -84,12 +111,83 @@ impl Chunk {
     }
 }
 
-pub fn calculate_from_bytes(bytes: &[u8]) -> u32 {
-    let mut reader = BufReader::new(bytes);
-    let mut buffer = [0_u8; 4];
-    reader.read_exact(&mut buffer).unwrap();
-    u32::from_be_bytes(buffer)
+fn checked_length(data: &[u8]) -> Result<u32> {
+    match u32::try_from(data.len()) {
+        Ok(length) if length <= Chunk::MAX_LENGTH => Ok(length),
+        _ => Err(Error::DataTooLong { length: data.len() }),
+    }
+}
+
+fn checksum(chunk_type: ChunkType, data: &[u8]) -> u32 {
+    let mut hasher = Crc32::new();
+    hasher.update(&chunk_type.bytes());
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Reads the big endian u32 starting at `offset`
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let buffer: [u8; 4] = bytes
+        .get(offset..offset + 4)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(Error::Truncated { offset })?;
+    Ok(u32::from_be_bytes(buffer))
 }
+
+/// Splits the chunk starting at `offset` into its type, data and stored crc.
+/// Nothing is copied and the crc is not checked, but every length is
+/// validated against the spec limit and the bytes actually available.
+pub(crate) fn read_chunk(bytes: &[u8], offset: usize) -> Result<(ChunkType, &[u8], u32)> {
+    if bytes.len() < offset + 12 {
+        return Err(Error::Truncated { offset });
+    }
+    let length = read_u32(bytes, offset)?;
+    if length > Chunk::MAX_LENGTH {
+        return Err(Error::LengthTooLarge { offset, length });
+    }
+    let available = bytes.len() - offset - 12;
+    if length as usize > available {
+        return Err(Error::ChunkOverrun {
+            offset,
+            length,
+            available,
+        });
+    }
+    let chunk_type: [u8; 4] = bytes[offset + 4..offset + 8].try_into().unwrap();
+    let data = &bytes[offset + 8..offset + 8 + length as usize];
+    let crc = read_u32(bytes, offset + 8 + length as usize)?;
+    Ok((ChunkType(chunk_type), data, crc))
+}
+/// Writes `data` as the `encoding` and `data` fields of a serialized map:
+/// the text itself when it is utf-8, base64 when it is not
+pub fn serialize_data<M: SerializeMap>(
+    map: &mut M,
+    data: &[u8],
+) -> std::result::Result<(), M::Error> {
+    match str::from_utf8(data) {
+        Ok(text) => {
+            map.serialize_entry("encoding", "utf-8")?;
+            map.serialize_entry("data", text)
+        }
+        Err(_) => {
+            map.serialize_entry("encoding", "base64")?;
+            map.serialize_entry("data", &BASE64_STANDARD.encode(data))
+        }
+    }
+}
+
+impl Serialize for Chunk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("type", &self.chunk_type)?;
+        map.serialize_entry("length", &self.length)?;
+        map.serialize_entry("crc", &self.crc)?;
+        map.serialize_entry("properties", &self.chunk_type.properties())?;
+        serialize_data(&mut map, &self.data)?;
+        map.end()
+    }
+}
+
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Chunk {{",)?;
@@ -106,23 +204,25 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Self> {
-        let crc_bytes = &bytes[bytes.len() - 4..];
-        let original_crc = calculate_from_bytes(crc_bytes); // crc provided
-        let checksum = crc(&bytes[4..bytes.len() - 4]);
+        let (chunk_type, data, original_crc) = read_chunk(bytes, 0)?; // crc provided
+        let end = 12 + data.len();
+        if end != bytes.len() {
+            return Err(Error::TrailingData { offset: end });
+        }
+        let checksum = crc(&bytes[4..end - 4]);
         match original_crc == checksum {
-            true => {
-                let length = calculate_from_bytes(&bytes[0..4]);
-                let data = bytes[8..bytes.len() - 4].to_vec();
-                let chunk_type = &bytes[4..8];
-                let chunk_array: [u8; 4] = chunk_type.try_into().unwrap();
-                Ok(Chunk {
-                    length,
-                    chunk_type: ChunkType(chunk_array),
-                    data,
-                    crc: checksum,
-                })
-            }
-            false => Err(Error::from("invalid chunk")),
+            true => Ok(Chunk::from_parts_unchecked(
+                data.len() as u32,
+                chunk_type,
+                data.to_vec(),
+                checksum,
+            )),
+            false => Err(Error::CrcMismatch {
+                chunk_type,
+                offset: 0,
+                expected: checksum,
+                found: original_crc,
+            }),
         }
     }
 }
@@ -232,7 +332,70 @@ mod tests {
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
 
-        assert!(chunk.is_err());
+        assert!(matches!(
+            chunk,
+            Err(Error::CrcMismatch {
+                expected: 2882656334,
+                found: 2882656333,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_set_data() {
+        let mut chunk = testing_chunk();
+        chunk.set_data(b"new message".to_vec()).unwrap();
+        let expected = Chunk::new(chunk.chunk_type(), b"new message".to_vec());
+        assert_eq!(chunk, expected);
+        assert_eq!(chunk.length(), 11);
+        assert_eq!(
+            Chunk::try_from(chunk.as_bytes().as_ref()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_from_parts_unchecked() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let chunk = Chunk::from_parts_unchecked(99, chunk_type, b"data".to_vec(), 1);
+        assert_eq!(chunk.length(), 99);
+        assert_eq!(chunk.crc(), 1);
+        assert_eq!(&chunk.as_bytes()[..4], &[0, 0, 0, 99]);
+        assert!(Chunk::try_from(chunk.as_bytes().as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_short_chunk_from_bytes() {
+        let chunk = Chunk::try_from([0, 0, 0, 0, 82, 117].as_ref());
+        assert!(matches!(chunk, Err(Error::Truncated { offset: 0 })));
+    }
+
+    #[test]
+    fn test_chunk_with_trailing_bytes() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        chunk_data.push(0);
+        let chunk = Chunk::try_from(chunk_data.as_ref());
+        assert!(matches!(chunk, Err(Error::TrailingData { offset: 54 })));
+    }
+
+    #[test]
+    fn test_serialize_chunk() {
+        let json = serde_json::to_value(testing_chunk()).unwrap();
+        assert_eq!(json["type"], "RuSt");
+        assert_eq!(json["length"], 42);
+        assert_eq!(json["crc"], 2882656334u32);
+        assert_eq!(json["properties"]["critical"], true);
+        assert_eq!(json["encoding"], "utf-8");
+        assert_eq!(json["data"], "This is where your secret message will be!");
+    }
+
+    #[test]
+    fn test_serialize_binary_chunk() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![0xff, 0, 1]);
+        let json = serde_json::to_value(chunk).unwrap();
+        assert_eq!(json["encoding"], "base64");
+        assert_eq!(json["data"], "/wAB");
     }
 
     #[test]
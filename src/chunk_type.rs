@@ -2,15 +2,136 @@ use crate::{Error, Result};
 /// png file always start with signature
 /// first eight byte of png type always contain these eight bytes  137 80 78 71 13 10 26 10
 /// each chunk consist of four parts
+use serde::{Serialize, Serializer};
 use std::convert::TryFrom;
 use std::fmt;
-use std::str;
 use std::str::FromStr;
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+
+/// What the case of each letter in a chunk type says about the chunk.
+/// An uppercase first letter makes it critical, an uppercase second letter
+/// public, the third letter must be uppercase for the type to be valid, and
+/// a lowercase fourth letter makes it safe to copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ChunkProperties {
+    pub critical: bool,
+    pub public: bool,
+    pub reserved_bit_valid: bool,
+    pub safe_to_copy: bool,
+}
+
+impl ChunkProperties {
+    /// ancillary, private and safe to copy: viewers ignore the chunk and
+    /// editors keep it, which is what a hidden message wants
+    pub const SECRET: ChunkProperties = ChunkProperties {
+        critical: false,
+        public: false,
+        reserved_bit_valid: true,
+        safe_to_copy: true,
+    };
+}
+
+impl fmt::Display for ChunkProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let critical = if self.critical {
+            "critical"
+        } else {
+            "ancillary"
+        };
+        let public = if self.public { "public" } else { "private" };
+        let copy = if self.safe_to_copy {
+            "safe-to-copy"
+        } else {
+            "unsafe-to-copy"
+        };
+        let reserved = if self.reserved_bit_valid {
+            ""
+        } else {
+            " reserved-bit-invalid"
+        };
+        // pad so the properties can line up in columns
+        f.pad(&format!("{} {} {}{}", critical, public, copy, reserved))
+    }
+}
+
+/// The chunk types defined by the png specification and the registered
+/// extensions, with what each one holds
+const REGISTERED: [(&[u8; 4], &str); 34] = [
+    (b"IHDR", "image header"),
+    (b"PLTE", "palette"),
+    (b"IDAT", "image data"),
+    (b"IEND", "image trailer"),
+    (b"acTL", "animation control"),
+    (b"bKGD", "background colour"),
+    (b"cHRM", "primary chromaticities"),
+    (b"cICP", "coding-independent code points"),
+    (b"cLLI", "content light level"),
+    (b"dSIG", "digital signature"),
+    (b"eXIf", "exif data"),
+    (b"fcTL", "frame control"),
+    (b"fdAT", "frame data"),
+    (b"fRAc", "fractal image parameters"),
+    (b"gAMA", "image gamma"),
+    (b"gIFg", "gif graphic control extension"),
+    (b"gIFt", "gif plain text extension"),
+    (b"gIFx", "gif application extension"),
+    (b"hIST", "palette histogram"),
+    (b"iCCP", "embedded icc profile"),
+    (b"iTXt", "international text"),
+    (b"mDCV", "mastering display colour volume"),
+    (b"oFFs", "image offset"),
+    (b"pCAL", "pixel calibration"),
+    (b"pHYs", "physical pixel dimensions"),
+    (b"sBIT", "significant bits"),
+    (b"sCAL", "physical scale"),
+    (b"sPLT", "suggested palette"),
+    (b"sRGB", "standard rgb colour space"),
+    (b"sTER", "stereo image indicator"),
+    (b"tEXt", "textual data"),
+    (b"tIME", "last modification time"),
+    (b"tRNS", "transparency"),
+    (b"zTXt", "compressed textual data"),
+];
+
+/// A chunk type. Building one from its public field skips the checks of
+/// `FromStr` and `TryFrom`, use [`ChunkType::is_valid`] on such a type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChunkType(pub [u8; 4]); //tuple type stuct a valid type chunk is equal to 4 bytes
 
 impl ChunkType {
+    /// Builds a valid chunk type from four letters, changing their case to
+    /// give it the requested properties
+    pub fn with_properties(name: &str, properties: ChunkProperties) -> Result<Self> {
+        let mut bytes: [u8; 4] = name
+            .as_bytes()
+            .try_into()
+            .map_err(|_| Error::InvalidChunkType)?;
+        if !properties.reserved_bit_valid || !bytes.iter().all(|&b| ChunkType::is_valid_byte(b)) {
+            return Err(Error::InvalidChunkType);
+        }
+        let uppercase = [
+            properties.critical,
+            properties.public,
+            true,
+            !properties.safe_to_copy,
+        ];
+        for (byte, upper) in bytes.iter_mut().zip(uppercase) {
+            *byte = match upper {
+                true => byte.to_ascii_uppercase(),
+                false => byte.to_ascii_lowercase(),
+            };
+        }
+        Ok(ChunkType(bytes))
+    }
+
+    pub fn properties(&self) -> ChunkProperties {
+        ChunkProperties {
+            critical: self.is_critical(),
+            public: self.is_public(),
+            reserved_bit_valid: self.is_reserved_bit_valid(),
+            safe_to_copy: self.is_safe_to_copy(),
+        }
+    }
+
     pub fn bytes(&self) -> [u8; 4] {
         self.0
     }
@@ -31,7 +152,6 @@ impl ChunkType {
         false
     }
 
-    #[allow(dead_code)]
     pub fn is_reserved_bit_valid(&self) -> bool {
         let [_, _, c, _] = self.0;
         if c.is_ascii_uppercase() {
@@ -48,39 +168,47 @@ impl ChunkType {
         true
     }
 
-    #[allow(dead_code)]
     pub fn is_valid_byte(byte: u8) -> bool {
         if byte.is_ascii_alphabetic() {
             return true;
         }
         false
     }
-    pub fn to_string(&self) -> String {
-        str::from_utf8(&self.0).unwrap().to_owned()
-    }
 
+    /// four ascii letters with the reserved bit valid
     pub fn is_valid(&self) -> bool {
-        let [_, _, c, _] = self.0;
-        if c.is_ascii_uppercase() {
-            return true;
+        self.0.iter().all(|&b| ChunkType::is_valid_byte(b)) && self.is_reserved_bit_valid()
+    }
+
+    /// What the chunk holds, for the types defined by the png specification
+    /// and the registered extensions
+    pub fn registered_name(&self) -> Option<&'static str> {
+        REGISTERED
+            .iter()
+            .find(|(bytes, _)| **bytes == self.0)
+            .map(|&(_, name)| name)
+    }
+
+    /// Refuses a critical type for a hidden message, viewers must understand
+    /// every critical chunk and reject the image otherwise. Public types are
+    /// allowed but risky, viewers that know one will try to read the
+    /// message as the data they expect there.
+    pub fn check_for_message(&self) -> Result<()> {
+        match self.is_critical() {
+            true => Err(Error::CriticalChunkType(*self)),
+            false => Ok(()),
         }
-        false
     }
 }
 impl FromStr for ChunkType {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let bytes = s.as_bytes();
-        for i in bytes {
-            if !i.is_ascii_alphabetic() {
-                return Err(Error::from("not a valid string"));
-            }
-        }
-        let chunk_array: [u8; 4] = bytes
+        let chunk_array: [u8; 4] = s
+            .as_bytes()
             .try_into()
-            .map_err(|_| Error::from("not a valid string"))?;
-        Ok(ChunkType(chunk_array))
+            .map_err(|_| Error::InvalidChunkType)?;
+        ChunkType::try_from(chunk_array)
     }
 }
 
@@ -88,17 +216,35 @@ impl TryFrom<[u8; 4]> for ChunkType {
     type Error = Error;
     fn try_from(bytes: [u8; 4]) -> Result<Self> {
         let chunk = ChunkType(bytes);
-        match chunk.is_valid() {
+        if !bytes.iter().all(|&b| ChunkType::is_valid_byte(b)) {
+            return Err(Error::InvalidChunkType);
+        }
+        match chunk.is_reserved_bit_valid() {
             true => Ok(chunk),
-            false => Err(Error::from("bytes is invalid bytes")),
+            false => Err(Error::ReservedBitInvalid(chunk)),
         }
     }
 }
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self.0)
+        // types read from a damaged file may hold any byte
+        for &byte in &self.0 {
+            match byte.is_ascii_graphic() {
+                true => write!(f, "{}", byte as char)?,
+                false => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serialized as its four letters, escaped like [`Display`](fmt::Display)
+impl Serialize for ChunkType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -149,9 +295,20 @@ mod tests {
         assert!(chunk.is_reserved_bit_valid());
     }
 
+    #[test]
+    pub fn test_invalid_chunk_types_say_why() {
+        let error = ChunkType::from_str("rust").unwrap_err();
+        assert!(matches!(error, Error::ReservedBitInvalid(chunk) if chunk.bytes() == *b"rust"));
+        assert!(error.to_string().contains("reserved bit"));
+        assert!(matches!(
+            ChunkType::from_str("ru5t"),
+            Err(Error::InvalidChunkType)
+        ));
+    }
+
     #[test]
     pub fn test_chunk_type_is_reserved_bit_invalid() {
-        let chunk = ChunkType::from_str("Rust").unwrap();
+        let chunk = ChunkType(*b"Rust");
         assert!(!chunk.is_reserved_bit_valid());
     }
 
@@ -175,17 +332,90 @@ mod tests {
 
     #[test]
     pub fn test_invalid_chunk_is_valid() {
-        let chunk = ChunkType::from_str("Rust").unwrap();
+        let chunk = ChunkType(*b"Rust");
         assert!(!chunk.is_valid());
 
         let chunk = ChunkType::from_str("Ru1t");
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_from_str_and_try_from_agree() {
+        for name in ["RuSt", "Rust", "Ru1t", "R St", "RuS\u{e9}", "RuS", "RuStt"] {
+            let from_str = ChunkType::from_str(name).ok();
+            let from_bytes = <[u8; 4]>::try_from(name.as_bytes())
+                .ok()
+                .and_then(|bytes| ChunkType::try_from(bytes).ok());
+            assert_eq!(from_str, from_bytes, "{}", name);
+        }
+        assert!(ChunkType::try_from(*b"Ru\x20t").is_err());
+        assert!(ChunkType::try_from([82, 117, 83, 0]).is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_properties() {
+        let properties = ChunkType::from_str("coOl").unwrap().properties();
+        assert_eq!(properties, ChunkProperties::SECRET);
+
+        let properties = ChunkType::from_str("IHDR").unwrap().properties();
+        assert!(properties.critical);
+        assert!(properties.public);
+        assert!(!properties.safe_to_copy);
+    }
+
+    #[test]
+    pub fn test_chunk_type_with_properties() {
+        let chunk = ChunkType::with_properties("COOL", ChunkProperties::SECRET).unwrap();
+        assert_eq!(chunk.to_string(), "coOl");
+        assert_eq!(chunk.properties(), ChunkProperties::SECRET);
+
+        let properties = ChunkProperties {
+            critical: true,
+            public: true,
+            reserved_bit_valid: true,
+            safe_to_copy: false,
+        };
+        let chunk = ChunkType::with_properties("idat", properties).unwrap();
+        assert_eq!(chunk.to_string(), "IDAT");
+
+        let properties = ChunkProperties {
+            reserved_bit_valid: false,
+            ..properties
+        };
+        assert!(ChunkType::with_properties("idat", properties).is_err());
+        assert!(ChunkType::with_properties("id4t", ChunkProperties::SECRET).is_err());
+    }
+
+    #[test]
+    pub fn test_serialize_chunk_type() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        assert_eq!(serde_json::to_string(&chunk_type).unwrap(), "\"RuSt\"");
+        let properties = serde_json::to_value(chunk_type.properties()).unwrap();
+        assert_eq!(properties["critical"], true);
+        assert_eq!(properties["public"], false);
+        assert_eq!(properties["safe_to_copy"], true);
+    }
+
+    #[test]
+    pub fn test_chunk_properties_display() {
+        let properties = ChunkType::from_str("IHDR").unwrap().properties();
+        assert_eq!(properties.to_string(), "critical public unsafe-to-copy");
+        assert_eq!(
+            ChunkProperties::SECRET.to_string(),
+            "ancillary private safe-to-copy"
+        );
+    }
+
+    #[test]
+    pub fn test_chunk_type_display_escapes_bytes() {
+        assert_eq!(ChunkType([82, 117, 0, 255]).to_string(), "Ru\\x00\\xff");
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
         assert_eq!(&chunk.to_string(), "RuSt");
+        assert_eq!(format!("{}", chunk), "RuSt");
     }
 
     #[test]
@@ -195,4 +425,32 @@ mod tests {
         let _chunk_string = format!("{}", chunk_type_1);
         let _are_chunks_equal = chunk_type_1 == chunk_type_2;
     }
+
+    #[test]
+    fn test_registered_name() {
+        let chunk = ChunkType::from_str("tEXt").unwrap();
+        assert_eq!(chunk.registered_name(), Some("textual data"));
+        let chunk = ChunkType::from_str("teXt").unwrap();
+        assert_eq!(chunk.registered_name(), None);
+        assert!(REGISTERED
+            .iter()
+            .all(|(bytes, _)| ChunkType::try_from(**bytes).is_ok()));
+    }
+
+    #[test]
+    fn test_check_for_message() {
+        for name in ["IDAT", "CoOl"] {
+            let chunk = ChunkType::from_str(name).unwrap();
+            assert!(matches!(
+                chunk.check_for_message(),
+                Err(Error::CriticalChunkType(found)) if found == chunk
+            ));
+        }
+        for name in ["tEXt", "ruSt"] {
+            assert!(ChunkType::from_str(name)
+                .unwrap()
+                .check_for_message()
+                .is_ok());
+        }
+    }
 }
@@ -1,80 +1,1491 @@
-use crate::args::{DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs};
-use crate::chunk::Chunk;
-use crate::chunk_type::ChunkType;
-use crate::png::Png;
-use crate::{Error, Result};
+use crate::args::{
+    DecodeArgs, EncodeArgs, Format, InfoArgs, KeygenArgs, PrintArgs, RemoveArgs, UpdateArgs,
+    VerifyArgs,
+};
+use filetime::FileTime;
+use memmap2::Mmap;
+use secret_pics::chunk::serialize_data;
+use secret_pics::{
+    ChunkProperties, ChunkReader, ChunkRef, ChunkType, ChunkWriter, Compression, CrcRepair,
+    Envelope, Error, Flags, Identity, ImageHasher, KdfParams, KeySource, PngRef, PublicKey, Result,
+    SignatureCheck, SigningKey, VerifyingKey,
+};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
 use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// lets scripts pass the passphrase without a prompt
+const PASSPHRASE_VAR: &str = "SECRET_PICS_PASSPHRASE";
 /// Encodes a message into a PNG file and saves the result
-pub fn encode(args: EncodeArgs) -> Result<()> {
-    //creating file using from file method available in png struct
-    let mut png = Png::from_file(&args.file_path)?;
-    // creating chunk type because Chunk take chunk type
-    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
-    // creating new chunk by the data use provided
-    let chunk = Chunk::new(chunk_type, args.message.as_bytes().to_vec());
-    // adding our secret chunk to the png chunk
-    png.append_chunk(chunk);
-    let _file_name = args.file_path.file_name().unwrap();
-    // handling output path if user provided any
-    let mut file = match &args.output_file {
-        Some(path) => {
-            if path.exists() {
-                return Err(Error::from("file already exists"));
-            } else {
-                fs::File::create(path)?
-            }
+pub fn encode(mut args: EncodeArgs) -> Result<()> {
+    if args.file.is_some() || args.stdin {
+        if args.output_file.is_some() {
+            return Err(invalid_input(
+                "a message cannot be given along with --file or --stdin",
+            ));
+        }
+        // the lone positional left is the deprecated output file
+        args.output_file = args.message.take().map(PathBuf::from);
+        if args.output_file.is_some() && (args.save.output.is_some() || args.save.backup.is_some())
+        {
+            return Err(invalid_input(
+                "the output file argument cannot be used with -o or --backup",
+            ));
         }
-        None => fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&args.file_path)?,
+    }
+    if !args.force {
+        check_chunk_type(&args.chunk_type)?;
+    }
+    if is_stdio(&args.file_path) && args.stdin {
+        return Err(invalid_input(
+            "stdin cannot hold both the image and the message",
+        ));
+    }
+    // stdin can only be read once, so an image piped in is kept in memory
+    // when the image digest needs a pass over it before the encoding
+    let piped = match is_stdio(&args.file_path) && args.bind_image {
+        true => Some(read_input(&args.file_path, false)?),
+        false => None,
+    };
+    let input = || -> Result<Box<dyn Read + '_>> {
+        match &piped {
+            Some(bytes) => Ok(Box::new(&bytes[..])),
+            None => open_input(&args.file_path),
+        }
+    };
+    let image_digest = match args.bind_image {
+        true => Some(reader_image_digest(input()?, args.input.repair)?),
+        false => None,
     };
-    file.write_all(&png.as_bytes())?;
+    let payload = payload(&args, image_digest)?;
+    if args.output_file.is_some() {
+        eprintln!("warning: the output file argument is deprecated, use -o PATH instead");
+    }
+    let output = args.output_file.as_deref().or(args.save.output.as_deref());
+    save(&args.file_path, output, args.save.backup.as_deref(), |o| {
+        encode_stream(&args, input()?, &payload, o)
+    })
+}
+
+/// Refuses a chunk type that would break the image before any work is
+/// done, and warns about one that viewers might misread
+fn check_chunk_type(chunk_type: &str) -> Result<()> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    chunk_type.check_for_message()?;
+    if let Some(warning) = chunk_type_warning(chunk_type)? {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(())
+}
+
+fn chunk_type_warning(chunk_type: ChunkType) -> Result<Option<String>> {
+    if !chunk_type.is_public() {
+        return Ok(None);
+    }
+    let known = match chunk_type.registered_name() {
+        Some(name) => format!(" for {}", name),
+        None => String::new(),
+    };
+    let private = ChunkType::with_properties(&chunk_type.to_string(), ChunkProperties::SECRET)?;
+    Ok(Some(format!(
+        "{} is a public chunk type{}, viewers may misread the message; \
+         a private type like {} is safer",
+        chunk_type, known, private
+    )))
+}
+
+/// The envelope to hide, holding the message argument, a whole file or all
+/// of stdin, compressed, encrypted and signed when asked to
+fn payload(args: &EncodeArgs, image_digest: Option<[u8; 32]>) -> Result<Vec<u8>> {
+    let mut envelope = Envelope {
+        content_type: args.content_type.clone(),
+        ..Envelope::now(Vec::new())
+    };
+    if let Some(path) = &args.file {
+        envelope.payload = fs::read(path)?;
+        envelope.filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+    } else if args.stdin {
+        io::stdin().lock().read_to_end(&mut envelope.payload)?;
+    } else {
+        // clap makes sure one of the three is given
+        envelope.payload = args.message.clone().unwrap_or_default().into_bytes();
+        if envelope.content_type.is_none() {
+            envelope.content_type = Some("text/plain; charset=utf-8".to_string());
+        }
+    }
+    if let Some(compression) = args.compress {
+        envelope = secret_pics::compress::compress(&envelope, compression.into())?;
+    }
+    if args.encrypt {
+        let passphrase = passphrase(true)?;
+        envelope = secret_pics::crypto::encrypt(
+            &envelope,
+            passphrase.as_bytes(),
+            args.cipher.into(),
+            KdfParams::default(),
+        )?;
+    } else if !args.recipient.is_empty() {
+        let mut recipients = Vec::new();
+        for recipient in &args.recipient {
+            recipients.extend(read_recipients(recipient)?);
+        }
+        envelope = secret_pics::crypto::encrypt_to(&envelope, &recipients, args.cipher.into())?;
+    }
+    // signing last leaves the signature outside any encryption, so anyone
+    // can verify who planted the message without being able to read it
+    if let Some(path) = &args.sign {
+        let key = read_keys::<SigningKey>(&fs::read_to_string(path)?)?
+            .into_iter()
+            .next()
+            .ok_or(Error::InvalidKey)?;
+        envelope = secret_pics::signature::sign(&envelope, &key, image_digest)?;
+    }
+    envelope.to_bytes()
+}
+
+/// Hashes the image data of a png without loading it all at once
+fn reader_image_digest(input: impl Read, repair: bool) -> Result<[u8; 32]> {
+    let reader = chunk_reader(input, repair)?;
+    let mut hasher = ImageHasher::new();
+    for chunk in reader {
+        let chunk = chunk?;
+        hasher.update(chunk.chunk_type(), chunk.data());
+    }
+    Ok(hasher.finish())
+}
+
+fn image_digest(png: &PngRef) -> [u8; 32] {
+    let mut hasher = ImageHasher::new();
+    for chunk in png.chunks() {
+        hasher.update(chunk.chunk_type(), chunk.data());
+    }
+    hasher.finish()
+}
+
+/// A signature check as reported by `decode` and `verify`
+#[derive(Serialize)]
+struct SignatureReport {
+    signer: String,
+    valid: bool,
+    image_bound: bool,
+    /// whether the image still has the data the message was signed in
+    image_matches: Option<bool>,
+}
+
+impl SignatureReport {
+    fn new(check: &SignatureCheck, png: &PngRef) -> SignatureReport {
+        SignatureReport {
+            signer: check.signer.to_string(),
+            valid: check.valid,
+            image_bound: check.image_digest.is_some(),
+            image_matches: check.image_digest.map(|digest| digest == image_digest(png)),
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.valid && self.image_matches != Some(false)
+    }
+
+    fn describe(&self) -> String {
+        let signature = if self.valid { "valid" } else { "INVALID" };
+        let image = match self.image_matches {
+            None => "",
+            Some(true) => ", bound to this image",
+            Some(false) => ", bound to a DIFFERENT image",
+        };
+        format!("{} ({} signature{})", self.signer, signature, image)
+    }
+}
+
+fn encode_stream(
+    args: &EncodeArgs,
+    input: impl Read,
+    payload: &[u8],
+    output: &mut dyn Write,
+) -> Result<()> {
+    let mut reader = chunk_reader(input, args.input.repair)?;
+    let mut writer = ChunkWriter::new(output)?;
+    let (chunk_type, placement) = (args.chunk_type.as_str(), args.placement.into());
+    let (input, output) = (&mut reader, &mut writer);
+    match (args.replace, args.split) {
+        _ if args.force => secret_pics::embed_stream_forced(
+            input,
+            output,
+            chunk_type,
+            payload,
+            args.split,
+            args.replace,
+            placement,
+        ),
+        (false, None) => secret_pics::embed_stream(input, output, chunk_type, payload, placement),
+        (false, Some(size)) => {
+            secret_pics::embed_stream_split(input, output, chunk_type, payload, size, placement)
+        }
+        (true, None) => secret_pics::replace_stream(input, output, chunk_type, payload, placement),
+        (true, Some(size)) => {
+            secret_pics::replace_stream_split(input, output, chunk_type, payload, size, placement)
+        }
+    }?;
+    writer.finish()?;
+    report_repairs(reader.repairs());
     Ok(())
 }
 /// Searches for a message hidden in a PNG file and prints the message if one is found
-pub fn decode(args: DecodeArgs) -> Result<()> {
-    let png = Png::from_file(args.file_path)?;
-    let message = png.chunk_by_type(&args.chunk_type);
-    match message {
-        Some(data) => {
-            println!(
-                "Chunk: {} \nMessage: {:?}",
-                data.chunk_type.to_string(),
-                data.data_as_string().unwrap()
-            );
-            Ok(())
+pub fn decode(args: DecodeArgs, format: Format) -> Result<()> {
+    let bytes = read_input(&args.file_path, args.map.mmap)?;
+    let png = load_ref(&bytes, args.input.repair)?;
+    let messages = secret_pics::messages(&png, &args.chunk_type)?;
+    let indexes = pick_messages(&messages, args.all, args.index, "showing")?;
+    let mut passphrase = None;
+    let mut decoded = Vec::new();
+    for index in indexes {
+        decoded.push(decode_message(
+            &png,
+            &messages[index],
+            index,
+            &args,
+            &mut passphrase,
+        )?);
+    }
+    // clap keeps --all away from --out and --stdout, so there is one message
+    if let Some(path) = args.out.as_ref().filter(|path| !is_stdio(path)) {
+        return Ok(fs::write(path, decoded[0].message())?);
+    }
+    if args.stdout || args.out.is_some() {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(decoded[0].message())?;
+        return Ok(stdout.flush()?);
+    }
+    if format == Format::Json {
+        if args.all {
+            #[derive(Serialize)]
+            struct All<'a> {
+                messages: Vec<Decoded<'a>>,
+            }
+            return print_json(&All { messages: decoded });
+        }
+        return print_json(&decoded[0]);
+    }
+    for (i, decoded) in decoded.iter().enumerate() {
+        if i > 0 {
+            println!();
         }
-        None => Err(Error::from("chunk not found")),
+        decoded.print(&args.chunk_type, args.all);
     }
+    Ok(())
 }
 
-/// Removes a chunk from a PNG file and saves the result
-pub fn remove(args: RemoveArgs) -> Result<()> {
-    let mut png = Png::from_file(&args.file_path)?;
-    png.remove_chunk(&args.chunk_type as &str)?;
-    let mut file = fs::OpenOptions::new()
+/// The indexes of the messages `--all` or `--index` pick, the first one
+/// when neither is given, noting that there are others
+fn pick_messages(
+    messages: &[Vec<&ChunkRef>],
+    all: bool,
+    index: Option<usize>,
+    doing: &str,
+) -> Result<Range<usize>> {
+    let chunk_type = messages[0][0].chunk_type();
+    match (all, index) {
+        (true, _) => Ok(0..messages.len()),
+        (false, Some(index)) if index >= messages.len() => Err(Error::MessageNotFound {
+            chunk_type,
+            index,
+            count: messages.len(),
+        }),
+        (false, Some(index)) => Ok(index..index + 1),
+        (false, None) => {
+            if messages.len() > 1 {
+                eprintln!(
+                    "note: there are {} {} messages, {} the first, pass --all or --index to see the others",
+                    messages.len(),
+                    chunk_type,
+                    doing
+                );
+            }
+            Ok(0..1)
+        }
+    }
+}
+
+/// One message as `decode` reports it
+struct Decoded<'a> {
+    /// the chunk holding the message, or every piece of a split one
+    chunks: Vec<&'a ChunkRef<'a>>,
+    index: usize,
+    encrypted: bool,
+    compression: Option<String>,
+    signature: Option<SignatureReport>,
+    envelope: Option<Envelope>,
+    data: Cow<'a, [u8]>,
+}
+
+/// Serialized with the decoded message as its `encoding` and `data`, and
+/// where each chunk holding it sits in `chunks`
+impl Serialize for Decoded<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Stored {
+            offset: usize,
+            length: u32,
+            crc: u32,
+        }
+        let chunk_type = self.chunks[0].chunk_type();
+        let stored: Vec<Stored> = self
+            .chunks
+            .iter()
+            .map(|chunk| Stored {
+                offset: chunk.offset(),
+                length: chunk.length(),
+                crc: chunk.crc(),
+            })
+            .collect();
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("type", &chunk_type)?;
+        map.serialize_entry("properties", &chunk_type.properties())?;
+        map.serialize_entry("chunks", &stored)?;
+        map.serialize_entry("index", &self.index)?;
+        map.serialize_entry("pieces", &self.chunks.len())?;
+        map.serialize_entry("encrypted", &self.encrypted)?;
+        map.serialize_entry("compression", &self.compression)?;
+        map.serialize_entry("signature", &self.signature)?;
+        map.serialize_entry("envelope", &self.envelope.as_ref().map(Metadata))?;
+        serialize_data(&mut map, self.message())?;
+        map.end()
+    }
+}
+
+/// The envelope of a decoded message without the message itself, which
+/// [`Decoded`] already holds
+struct Metadata<'a>(&'a Envelope);
+
+impl Serialize for Metadata<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let Metadata(envelope) = self;
+        let mut map = serializer.serialize_map(Some(5))?;
+        map.serialize_entry("version", &Envelope::VERSION)?;
+        map.serialize_entry("flags", &envelope.flags)?;
+        map.serialize_entry("created_at", &envelope.created_at)?;
+        map.serialize_entry("content_type", &envelope.content_type)?;
+        map.serialize_entry("filename", &envelope.filename)?;
+        map.end()
+    }
+}
+
+impl Decoded<'_> {
+    /// the message itself, without its envelope
+    fn message(&self) -> &[u8] {
+        self.envelope
+            .as_ref()
+            .map_or(&self.data, |envelope| &envelope.payload)
+    }
+
+    fn print(&self, chunk_type: &str, with_index: bool) {
+        println!("Chunk: {} ", chunk_type);
+        if with_index {
+            println!("Index: {}", self.index);
+        }
+        if self.chunks.len() > 1 {
+            println!("Pieces: {}", self.chunks.len());
+        }
+        if self.encrypted {
+            println!("Encrypted: yes");
+        }
+        if let Some(compression) = &self.compression {
+            println!("Compressed: {}", compression);
+        }
+        if let Some(signature) = &self.signature {
+            println!("Signed by: {}", signature.describe());
+        }
+        if let Some(envelope) = &self.envelope {
+            print_metadata(envelope);
+        }
+        let message = self.message();
+        match str::from_utf8(message) {
+            Ok(text) => println!("Message: {:?}", text),
+            Err(_) => println!(
+                "Message: {} bytes of binary data, use --out or --stdout to save them",
+                message.len()
+            ),
+        }
+    }
+}
+
+/// Joins, checks the signature of, decrypts and decompresses one message,
+/// asking for the passphrase at most once for all of them
+fn decode_message<'a>(
+    png: &PngRef,
+    chunks: &[&'a ChunkRef<'a>],
+    index: usize,
+    args: &DecodeArgs,
+    passphrase: &mut Option<Zeroizing<String>>,
+) -> Result<Decoded<'a>> {
+    let data = secret_pics::join_message(chunks)?;
+    // chunks written before envelopes existed hold just the message
+    let mut envelope = match Envelope::try_from(data.as_ref()) {
+        Ok(envelope) => Some(envelope),
+        Err(Error::NotAnEnvelope) => None,
+        Err(err) => return Err(err),
+    };
+    let mut signature = None;
+    if let Some(signed) = envelope
+        .as_ref()
+        .filter(|envelope| envelope.flags.contains(Flags::SIGNED))
+    {
+        let (check, inner) = secret_pics::signature::verify(signed)?;
+        let report = SignatureReport::new(&check, png);
+        if !report.is_ok() {
+            if !args.ignore_signature {
+                return Err(Error::SignatureFailed);
+            }
+            eprintln!("warning: the signature check failed, run verify for details");
+        }
+        signature = Some(report);
+        envelope = Some(inner);
+    }
+    let encrypted = envelope
+        .as_ref()
+        .is_some_and(|envelope| envelope.flags.contains(Flags::ENCRYPTED));
+    if let Some(sealed) = envelope.as_ref().filter(|_| encrypted) {
+        envelope = Some(match KeySource::of(sealed)? {
+            KeySource::Passphrase => {
+                if passphrase.is_none() {
+                    *passphrase = Some(self::passphrase(false)?);
+                }
+                let passphrase = passphrase.as_ref().unwrap();
+                secret_pics::crypto::decrypt(sealed, passphrase.as_bytes())?
+            }
+            KeySource::Recipients => {
+                if args.identity.is_empty() {
+                    return Err(invalid_input(
+                        "the message is encrypted to recipients, pass --identity",
+                    ));
+                }
+                let mut identities = Vec::new();
+                for path in &args.identity {
+                    identities.extend(read_keys::<Identity>(&fs::read_to_string(path)?)?);
+                }
+                secret_pics::crypto::decrypt_with(sealed, &identities)?
+            }
+        });
+    }
+    let mut compression = None;
+    if let Some(compressed) = &envelope {
+        compression = Compression::of(compressed)?;
+        envelope = Some(secret_pics::compress::decompress(
+            compressed,
+            args.max_size,
+        )?);
+    }
+    Ok(Decoded {
+        chunks: chunks.to_vec(),
+        index,
+        encrypted,
+        compression: compression.map(|compression| compression.to_string()),
+        signature,
+        envelope,
+        data,
+    })
+}
+
+fn print_metadata(envelope: &Envelope) {
+    if let Some(content_type) = &envelope.content_type {
+        println!("Content type: {}", content_type);
+    }
+    if let Some(filename) = &envelope.filename {
+        println!("File name: {}", filename);
+    }
+    if let Some(created_at) = envelope.created_at {
+        println!("Created: {}", format_timestamp(created_at));
+    }
+    let flags: Vec<&str> = [
+        (Flags::COMPRESSED, "compressed"),
+        (Flags::ENCRYPTED, "encrypted"),
+    ]
+    .iter()
+    .filter(|(flag, _)| envelope.flags.contains(*flag))
+    .map(|(_, name)| *name)
+    .collect();
+    if !flags.is_empty() {
+        println!("Flags: {}", flags.join(", "));
+    }
+}
+
+/// Checks the signature of a message, or of every signed one, returning
+/// whether they all hold up
+pub fn verify(args: VerifyArgs, format: Format) -> Result<bool> {
+    let bytes = read_input(&args.file_path, args.map.mmap)?;
+    let png = PngRef::try_from(&bytes[..])?;
+    let messages = secret_pics::messages(&png, &args.chunk_type)?;
+    let indexes = pick_messages(&messages, args.all, args.index, "checking")?;
+    let signers = match &args.signer {
+        Some(signer) => Some(read_verifying_keys(signer)?),
+        None => None,
+    };
+    let mut verified = Vec::new();
+    for index in indexes {
+        let data = secret_pics::join_message(&messages[index])?;
+        let signed = Envelope::try_from(data.as_ref())
+            .and_then(|envelope| secret_pics::signature::verify(&envelope).map(|(check, _)| check));
+        let check = match signed {
+            // --all checks the signed messages among the others
+            Err(Error::NotSigned | Error::NotAnEnvelope) if args.all => continue,
+            // a message without an envelope has no signature either
+            Err(Error::NotAnEnvelope) => return Err(Error::NotSigned),
+            signed => signed?,
+        };
+        let signature = SignatureReport::new(&check, &png);
+        let trusted_signer = signers.as_ref().map(|keys| keys.contains(&check.signer));
+        let ok = signature.is_ok() && trusted_signer != Some(false);
+        verified.push(Verified {
+            index,
+            signature,
+            trusted_signer,
+            ok,
+        });
+    }
+    if verified.is_empty() {
+        return Err(Error::NotSigned);
+    }
+    let ok = verified.iter().all(|verified| verified.ok);
+    if format == Format::Json {
+        match args.all {
+            true => {
+                #[derive(Serialize)]
+                struct All {
+                    messages: Vec<Verified>,
+                }
+                print_json(&All { messages: verified })?;
+            }
+            false => print_json(&verified[0])?,
+        }
+        return Ok(ok);
+    }
+    for (i, verified) in verified.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        verified.print(args.all);
+    }
+    Ok(ok)
+}
+
+/// One signature check as `verify` reports it
+#[derive(Serialize)]
+struct Verified {
+    index: usize,
+    #[serde(flatten)]
+    signature: SignatureReport,
+    trusted_signer: Option<bool>,
+    ok: bool,
+}
+
+impl Verified {
+    fn print(&self, with_index: bool) {
+        if with_index {
+            println!("Index: {}", self.index);
+        }
+        let report = &self.signature;
+        println!("Signer: {}", report.signer);
+        println!(
+            "Signature: {}",
+            if report.valid { "valid" } else { "INVALID" }
+        );
+        let image = match report.image_matches {
+            None => "not bound",
+            Some(true) => "matches",
+            Some(false) => "CHANGED since signing",
+        };
+        println!("Image: {}", image);
+        if let Some(trusted) = self.trusted_signer {
+            println!("Expected signer: {}", if trusted { "yes" } else { "NO" });
+        }
+    }
+}
+
+/// A `--signer` is either a verifying key or a file of them
+fn read_verifying_keys(signer: &str) -> Result<Vec<VerifyingKey>> {
+    if signer.starts_with("spver:") {
+        return Ok(vec![VerifyingKey::from_str(signer)?]);
+    }
+    read_keys(&fs::read_to_string(signer)?)
+}
+
+/// Generates an identity or signing key and writes it, along with its
+/// public key
+pub fn keygen(args: KeygenArgs, format: Format) -> Result<()> {
+    let (public_key, secret) = if args.signing {
+        let key = SigningKey::generate()?;
+        (key.verifying_key().to_string(), key.to_secret_string())
+    } else {
+        let identity = Identity::generate()?;
+        (
+            identity.public_key().to_string(),
+            identity.to_secret_string(),
+        )
+    };
+    let mut public_path = args.identity_path.clone().into_os_string();
+    public_path.push(".pub");
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&args.identity_path)?;
+    writeln!(file, "# public key: {}", public_key)?;
+    writeln!(file, "{}", secret.as_str())?;
+    fs::OpenOptions::new()
         .write(true)
-        .truncate(true)
-        .open(&args.file_path)?;
-    file.write_all(&png.as_bytes())?;
+        .create_new(true)
+        .open(&public_path)
+        .and_then(|mut file| writeln!(file, "{}", public_key))?;
+
+    if format == Format::Json {
+        #[derive(Serialize)]
+        struct Generated<'a> {
+            public_key: String,
+            identity_file: &'a Path,
+            public_key_file: &'a Path,
+        }
+        return print_json(&Generated {
+            public_key,
+            identity_file: &args.identity_path,
+            public_key_file: Path::new(&public_path),
+        });
+    }
+    println!("{}", public_key);
     Ok(())
 }
 
+/// A `--recipient` is either a public key or a file of them
+fn read_recipients(recipient: &str) -> Result<Vec<PublicKey>> {
+    if recipient.starts_with("sppub:") {
+        return Ok(vec![PublicKey::from_str(recipient)?]);
+    }
+    read_keys(&fs::read_to_string(recipient)?)
+}
+
+/// Parses one key per line, skipping blank lines and `#` comments
+fn read_keys<K: FromStr<Err = Error>>(text: &str) -> Result<Vec<K>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(K::from_str)
+        .collect()
+}
+
+/// Reads the passphrase from the environment or prompts for it on the
+/// terminal, twice when `confirm` is set
+fn passphrase(confirm: bool) -> Result<Zeroizing<String>> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Passphrase: ")?);
+    if confirm {
+        if passphrase.is_empty() {
+            return Err(invalid_input("the passphrase is empty"));
+        }
+        let again = Zeroizing::new(rpassword::prompt_password("Repeat passphrase: ")?);
+        if again != passphrase {
+            return Err(invalid_input("the passphrases do not match"));
+        }
+    }
+    Ok(passphrase)
+}
+
+fn invalid_input(message: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message).into()
+}
+
+/// Formats seconds since the unix epoch as a utc date and time
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+    // days to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Removes a chunk from a PNG file and saves the result
+pub fn remove(args: RemoveArgs) -> Result<()> {
+    let (output, backup) = (args.save.output.as_deref(), args.save.backup.as_deref());
+    save(&args.file_path, output, backup, |output| {
+        let input = open_input(&args.file_path)?;
+        let mut reader = chunk_reader(input, args.input.repair)?;
+        let mut writer = ChunkWriter::new(output)?;
+        match (args.all, args.index) {
+            (true, _) => {
+                secret_pics::remove_messages_stream(&mut reader, &mut writer, &args.chunk_type)
+            }
+            (false, index) => secret_pics::remove_message_stream(
+                &mut reader,
+                &mut writer,
+                &args.chunk_type,
+                index.unwrap_or(0),
+            ),
+        }?;
+        writer.finish()?;
+        report_repairs(reader.repairs());
+        Ok(())
+    })
+}
+
+/// Replaces a message with a new one, or an edited copy of itself, keeping
+/// its chunks where they are in the file
+pub fn update(args: UpdateArgs) -> Result<()> {
+    // never mapped, the file is replaced while the png is still in use
+    let bytes = read_input(&args.file_path, false)?;
+    let png = load_ref(&bytes, args.input.repair)?;
+    let messages = secret_pics::messages(&png, &args.chunk_type)?;
+    let chunks = messages.get(args.index).ok_or(Error::MessageNotFound {
+        chunk_type: messages[0][0].chunk_type(),
+        index: args.index,
+        count: messages.len(),
+    })?;
+    let data = secret_pics::join_message(chunks)?;
+    let (old, raw) = match Envelope::try_from(data.as_ref()) {
+        Ok(envelope) => (envelope, false),
+        Err(Error::NotAnEnvelope) => (Envelope::new(data.to_vec()), true),
+        Err(err) => return Err(err),
+    };
+    // re-sealing would need the keys it was made with, encode --replace can
+    // take them
+    if old.flags.contains(Flags::ENCRYPTED) || old.flags.contains(Flags::SIGNED) {
+        return Err(invalid_input(
+            "the message is encrypted or signed, change it with encode --replace",
+        ));
+    }
+    let compression = Compression::of(&old)?;
+    let old = secret_pics::compress::decompress(&old, Compression::DEFAULT_LIMIT)?;
+    let payload = if args.edit {
+        let text = str::from_utf8(&old.payload)
+            .map_err(|_| invalid_input("the message is not text and cannot be edited"))?;
+        let edited = edit(text)?;
+        if edited == text {
+            eprintln!("the message is unchanged");
+            return Ok(());
+        }
+        edited.into_bytes()
+    } else {
+        args.message.unwrap_or_default().into_bytes()
+    };
+    // a message written before envelopes existed stays without one
+    let data = match raw {
+        true => payload,
+        false => {
+            let mut envelope = Envelope {
+                created_at: old.created_at,
+                content_type: old.content_type,
+                filename: old.filename,
+                ..Envelope::new(payload)
+            };
+            if let Some(compression) = compression {
+                envelope = secret_pics::compress::compress(&envelope, compression)?;
+            }
+            envelope.to_bytes()?
+        }
+    };
+    let mut png = png.to_png();
+    secret_pics::update_message(&mut png, &args.chunk_type, args.index, &data)?;
+    let (output, backup) = (args.save.output.as_deref(), args.save.backup.as_deref());
+    save(&args.file_path, output, backup, |output| {
+        png.write_to(output)
+    })
+}
+
+/// Lets the user change `text` in their editor and returns the result
+fn edit(text: &str) -> Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // the file is only readable by us, it holds the secret in the clear
+    let mut file = tempfile::Builder::new()
+        .prefix("secret_pics-")
+        .suffix(".txt")
+        .tempfile()?;
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+    // the editor may come with arguments, like `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| invalid_input("the editor is empty"))?;
+    let status = process::Command::new(program)
+        .args(words)
+        .arg(file.path())
+        .status()?;
+    if !status.success() {
+        return Err(invalid_input(
+            "the editor failed, the message was not changed",
+        ));
+    }
+    let mut edited = String::from_utf8(fs::read(file.path())?)
+        .map_err(|_| invalid_input("the edited message is not valid utf-8"))?;
+    // most editors end the file with a newline the message did not have
+    if !text.ends_with('\n') && edited.ends_with('\n') {
+        edited.pop();
+    }
+    Ok(edited)
+}
+
 /// Prints all of the chunks in a PNG file
-pub fn print_chunks(args: PrintArgs) -> Result<()> {
-    let png = Png::from_file(&args.file_path)?;
-    let chunks = png.chunks();
-    for (i, chunk) in chunks.iter().enumerate() {
-        let data = format!(
-            "[\n{} -> Chunk Type: {}\nMessage: {:?}\n]",
-            i,
-            chunk.chunk_type.to_string(),
-            chunk.data_as_string().unwrap()
+pub fn print_chunks(args: PrintArgs, format: Format) -> Result<()> {
+    let bytes = read_input(&args.file_path, args.map.mmap)?;
+    // damaged chunks are listed with their crc status instead of refusing the file
+    let (png, repairs) = PngRef::parse_repairing(&bytes)?;
+    let listed = png.chunks().iter().filter(|chunk| is_listed(&args, chunk));
+    if format == Format::Json {
+        #[derive(Serialize)]
+        struct Listed<'a> {
+            #[serde(flatten)]
+            chunk: &'a ChunkRef<'a>,
+            stored_crc: u32,
+            crc_ok: bool,
+        }
+        #[derive(Serialize)]
+        struct Listing<'a> {
+            chunks: Vec<Listed<'a>>,
+        }
+        let chunks = listed
+            .map(|chunk| {
+                let repair = repairs
+                    .iter()
+                    .find(|repair| repair.offset == chunk.offset());
+                Listed {
+                    chunk,
+                    stored_crc: repair.map_or(chunk.crc(), |repair| repair.stored),
+                    crc_ok: repair.is_none(),
+                }
+            })
+            .collect();
+        return print_json(&Listing { chunks });
+    }
+    println!(
+        "{:<10} {:<10} {:<4}  {:<32}  {:<12}  DATA",
+        "OFFSET", "LENGTH", "TYPE", "PROPERTIES", "CRC"
+    );
+    for chunk in listed {
+        let crc = match repairs
+            .iter()
+            .find(|repair| repair.offset == chunk.offset())
+        {
+            Some(repair) => format!("bad {:08x}", repair.stored),
+            None => "ok".to_string(),
+        };
+        println!(
+            "{:<10} {:<10} {:<4}  {:<32}  {:<12}  {}",
+            chunk.offset(),
+            chunk.length(),
+            chunk.chunk_type(),
+            chunk.chunk_type().properties(),
+            crc,
+            preview(chunk.data())
         );
-        println!("{}", data);
     }
     Ok(())
 }
+
+fn is_listed(args: &PrintArgs, chunk: &ChunkRef) -> bool {
+    let properties = chunk.chunk_type().properties();
+    let name = chunk.chunk_type().bytes();
+    (!args.critical || properties.critical)
+        && (!args.ancillary || !properties.critical)
+        && (!args.public || properties.public)
+        && (!args.private || !properties.public)
+        && match &args.chunk_type {
+            Some(pattern) => glob_matches(pattern.as_bytes(), &name),
+            None => true,
+        }
+}
+
+/// Matches `name` against a pattern where `*` is any run of bytes and `?` any one byte
+fn glob_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_matches(rest, name) || (!name.is_empty() && glob_matches(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name_rest))) => glob_matches(rest, name_rest),
+        (Some((p, rest)), Some((n, name_rest))) => p == n && glob_matches(rest, name_rest),
+        _ => false,
+    }
+}
+
+/// Shows the start of a chunk's data as text when it is printable utf-8 and as hex when it is not
+fn preview(data: &[u8]) -> String {
+    const PREVIEW_CHARS: usize = 40;
+    const PREVIEW_BYTES: usize = 16;
+    let text = str::from_utf8(data)
+        .ok()
+        .filter(|text| !text.chars().any(|c| c.is_control() && !c.is_whitespace()));
+    if let Some(text) = text {
+        let mut shown: String = text.chars().take(PREVIEW_CHARS).collect();
+        let truncated = shown.len() < text.len();
+        shown = format!("{:?}", shown);
+        if truncated {
+            shown.push_str("...");
+        }
+        return shown;
+    }
+    let mut shown: Vec<String> = data
+        .iter()
+        .take(PREVIEW_BYTES)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if data.len() > PREVIEW_BYTES {
+        shown.push("...".to_string());
+    }
+    shown.join(" ")
+}
+
+/// Prints the image header fields and a summary of the chunks in a PNG file
+pub fn info(args: InfoArgs, format: Format) -> Result<()> {
+    #[derive(Serialize)]
+    struct Info<'a> {
+        size: usize,
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: u8,
+        interlaced: bool,
+        chunks: usize,
+        damaged_chunks: usize,
+        secret_chunks: Vec<&'a ChunkRef<'a>>,
+    }
+    let bytes = read_input(&args.file_path, args.map.mmap)?;
+    let (png, repairs) = PngRef::parse_repairing(&bytes)?;
+    // the ihdr is 13 bytes, anything shorter reads as zeroes rather than failing
+    let mut header = [0u8; 13];
+    if let Some(ihdr) = png.chunk_by_type("IHDR") {
+        let len = ihdr.data().len().min(header.len());
+        header[..len].copy_from_slice(&ihdr.data()[..len]);
+    }
+    let info = Info {
+        size: bytes.len(),
+        width: u32::from_be_bytes(header[0..4].try_into().unwrap()),
+        height: u32::from_be_bytes(header[4..8].try_into().unwrap()),
+        bit_depth: header[8],
+        color_type: header[9],
+        interlaced: header[12] != 0,
+        chunks: png.chunks().len(),
+        damaged_chunks: repairs.len(),
+        secret_chunks: png
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.chunk_type().properties() == ChunkProperties::SECRET)
+            .collect(),
+    };
+    if format == Format::Json {
+        return print_json(&info);
+    }
+    println!("size: {} bytes", info.size);
+    println!("dimensions: {}x{}", info.width, info.height);
+    println!("bit depth: {}", info.bit_depth);
+    println!("color type: {}", info.color_type);
+    println!("interlaced: {}", if info.interlaced { "yes" } else { "no" });
+    println!("chunks: {}", info.chunks);
+    println!("damaged chunks: {}", info.damaged_chunks);
+    for chunk in info.secret_chunks {
+        println!(
+            "secret chunk: {} ({} bytes)",
+            chunk.chunk_type(),
+            chunk.length()
+        );
+    }
+    Ok(())
+}
+
+/// Writes `value` to stdout as a single line of json
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value).map_err(io::Error::from)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Whether `path` is `-`, which stands for stdin or stdout
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Opens the file at `path`, or stdin for `-`
+fn open_input(path: &Path) -> Result<Box<dyn Read>> {
+    match is_stdio(path) {
+        true => Ok(Box::new(io::stdin().lock())),
+        false => Ok(Box::new(BufReader::new(fs::File::open(path)?))),
+    }
+}
+
+/// The bytes of a png, read into memory or mapped
+enum Input {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Input::Read(bytes) => bytes,
+            Input::Mapped(map) => map,
+        }
+    }
+}
+
+/// Reads all of the file at `path`, or of stdin for `-`. With `map` a file
+/// is mapped into memory instead, stdin can only be read.
+fn read_input(path: &Path, map: bool) -> Result<Input> {
+    if map && !is_stdio(path) {
+        let file = fs::File::open(path)?;
+        // SAFETY: the map is only ever read, and --mmap asks that the file
+        // is left alone while the command runs
+        return Ok(Input::Mapped(unsafe { Mmap::map(&file)? }));
+    }
+    let mut bytes = Vec::new();
+    open_input(path)?.read_to_end(&mut bytes)?;
+    Ok(Input::Read(bytes))
+}
+
+/// Starts reading a png a chunk at a time, recomputing bad crcs when
+/// `repair` is set
+fn chunk_reader<R: Read>(input: R, repair: bool) -> Result<ChunkReader<R>> {
+    let reader = ChunkReader::new(input)?;
+    match repair {
+        true => Ok(reader.repairing()),
+        false => Ok(reader),
+    }
+}
+
+/// Parses a png in place for commands that only read it
+fn load_ref(bytes: &[u8], repair: bool) -> Result<PngRef<'_>> {
+    if !repair {
+        return PngRef::try_from(bytes);
+    }
+    let (png, repairs) = PngRef::parse_repairing(bytes)?;
+    report_repairs(&repairs);
+    Ok(png)
+}
+
+fn report_repairs(repairs: &[CrcRepair]) {
+    for repair in repairs {
+        eprintln!(
+            "repaired crc of {} chunk at byte {}: {:#010x} -> {:#010x}",
+            repair.chunk_type, repair.offset, repair.stored, repair.computed
+        );
+    }
+}
+
+/// Writes the edited png to `output`, or else over the file at `path` it
+/// is read from, see [`write_replacing`]. A png read from stdin goes to
+/// stdout.
+fn save<F: FnOnce(&mut dyn Write) -> Result<()>>(
+    path: &Path,
+    output: Option<&Path>,
+    backup: Option<&str>,
+    write: F,
+) -> Result<()> {
+    match output {
+        Some(output) => write_output(output, write),
+        None if is_stdio(path) && backup.is_some() => Err(invalid_input(
+            "--backup needs a file to back up, the png comes from stdin",
+        )),
+        None if is_stdio(path) => write_output(path, write),
+        None => write_replacing(path, backup, write),
+    }
+}
+
+/// Writes a new file at `path` with [`write_new`], or to stdout for `-`
+fn write_output<F: FnOnce(&mut dyn Write) -> Result<()>>(path: &Path, write: F) -> Result<()> {
+    if !is_stdio(path) {
+        return write_new(path, write);
+    }
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    write(&mut stdout)?;
+    Ok(stdout.flush()?)
+}
+
+/// Creates the file at `path` like [`write_replacing`] does, but fails
+/// without touching it if it already exists
+fn write_new<F: FnOnce(&mut dyn Write) -> Result<()>>(path: &Path, write: F) -> Result<()> {
+    let file = write_temp(path, write)?;
+    file.as_file().sync_all()?;
+    file.persist_noclobber(path).map_err(|err| err.error)?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Replaces the file at `path` with what `write` writes, going through a
+/// temporary file in the same directory that is synced and then renamed
+/// over it, so a crash or a full disk leaves the old file or the new one
+/// and never half of each. The new file keeps the old one's permissions
+/// and timestamps. With a `backup` suffix the old file is kept too, under
+/// its name with the suffix added.
+fn write_replacing<F: FnOnce(&mut dyn Write) -> Result<()>>(
+    path: &Path,
+    backup: Option<&str>,
+    write: F,
+) -> Result<()> {
+    // replace the file a symlink points to rather than the link
+    let path = fs::canonicalize(path)?;
+    let metadata = fs::metadata(&path)?;
+    let file = write_temp(&path, write)?;
+    file.as_file().set_permissions(metadata.permissions())?;
+    filetime::set_file_handle_times(
+        file.as_file(),
+        Some(FileTime::from_last_access_time(&metadata)),
+        Some(FileTime::from_last_modification_time(&metadata)),
+    )?;
+    file.as_file().sync_all()?;
+    if let Some(suffix) = backup {
+        make_backup(&path, suffix)?;
+    }
+    file.persist(&path).map_err(|err| err.error)?;
+    sync_parent(&path);
+    Ok(())
+}
+
+/// Keeps the file at `path` under its name with `suffix` added. The backup
+/// is made under a temporary name and renamed over an older one, so there
+/// is always a complete backup once the original is replaced.
+fn make_backup(path: &Path, suffix: &str) -> Result<()> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(suffix);
+    let temp = tempfile::Builder::new()
+        .prefix(".secret_pics-")
+        .suffix(".bak")
+        .make_in(dir_of(path), |temp| match fs::hard_link(path, temp) {
+            // a hard link keeps the original as it is, inode and all, where
+            // the file system has them; a taken name is retried by tempfile
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+                fs::copy(path, temp)?;
+                fs::File::open(temp)?.sync_all()
+            }
+            linked => linked,
+        })?;
+    temp.persist(&backup).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Writes a temporary file next to `path`, removed again unless persisted
+fn write_temp<F: FnOnce(&mut dyn Write) -> Result<()>>(
+    path: &Path,
+    write: F,
+) -> Result<tempfile::NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".secret_pics-").suffix(".tmp");
+    // the umask applies as it would to a plainly created file
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+    let mut file = builder.tempfile_in(dir_of(path))?;
+    let mut writer = BufWriter::new(file.as_file_mut());
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    Ok(file)
+}
+
+/// Syncs the directory holding `path` so a rename into it is durable. Not
+/// every platform or file system can sync a directory, so failing is fine.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    let _ = fs::File::open(dir_of(path)).and_then(|dir| dir.sync_all());
+}
+
+/// The directory holding `path`, `.` for a bare file name
+fn dir_of(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches(b"IDAT", b"IDAT"));
+        assert!(glob_matches(b"I*", b"IHDR"));
+        assert!(glob_matches(b"*T", b"IDAT"));
+        assert!(glob_matches(b"?u?t", b"RuSt"));
+        assert!(glob_matches(b"*", b"tEXt"));
+        assert!(!glob_matches(b"I*", b"RuSt"));
+        assert!(!glob_matches(b"???", b"IEND"));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13:20 UTC");
+    }
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(preview(b"hello"), "\"hello\"");
+        assert_eq!(preview(&[0, 0, 177, 143]), "00 00 b1 8f");
+        assert_eq!(
+            preview(&[0xff; 20]),
+            format!("{} ...", vec!["ff"; 16].join(" "))
+        );
+        assert_eq!(
+            preview("a".repeat(50).as_bytes()),
+            format!("{:?}...", "a".repeat(40))
+        );
+    }
+
+    #[test]
+    fn test_write_replacing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+        let modified = FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(&path, modified).unwrap();
+
+        write_replacing(&path, Some(".bak"), |out| Ok(out.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(dir.path().join("image.png.bak")).unwrap(), b"old");
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(FileTime::from_last_modification_time(&metadata), modified);
+
+        // a failed write leaves the file and no temporary file behind
+        let failed = write_replacing(&path, None, |_| Err(invalid_input("failed")));
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // an older backup is replaced, and when no backup can be made the
+        // file is left alone
+        write_replacing(&path, Some(".bak"), |out| Ok(out.write_all(b"newer")?)).unwrap();
+        assert_eq!(fs::read(dir.path().join("image.png.bak")).unwrap(), b"new");
+        fs::create_dir(dir.path().join("image.png.stuck")).unwrap();
+        let failed = write_replacing(&path, Some(".stuck"), |out| Ok(out.write_all(b"lost")?));
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"newer");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_backup_of_stdin_is_refused() {
+        let saved = save(Path::new("-"), None, Some(".bak"), |_| Ok(()));
+        assert!(saved.is_err());
+    }
+
+    #[test]
+    fn test_write_new_does_not_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.png");
+        write_new(&path, |out| Ok(out.write_all(b"first")?)).unwrap();
+        assert!(write_new(&path, |out| Ok(out.write_all(b"second")?)).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+    }
+
+    /// Parses the arguments of one subcommand, after its name
+    fn parse<A: clap::Args + clap::FromArgMatches>(args: &[&str]) -> A {
+        let command = A::augment_args(clap::Command::new("secret_pics"));
+        A::from_arg_matches(&command.get_matches_from(args)).unwrap()
+    }
+
+    /// A png in a temporary directory holding one message per entry of
+    /// `messages`, all under coOl
+    fn testing_file(messages: &[&[u8]]) -> (tempfile::TempDir, String) {
+        let chunk = |name: &str, data: &[u8]| {
+            secret_pics::Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+        };
+        let mut chunks = vec![chunk("IHDR", &[0; 13])];
+        chunks.extend(messages.iter().map(|data| chunk("coOl", data)));
+        chunks.push(chunk("IEND", b""));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, secret_pics::Png::from_chunks(chunks).as_bytes()).unwrap();
+        (dir, path.to_string_lossy().into_owned())
+    }
+
+    /// What `decode --format json` prints for the message at `index`
+    fn decoded_json(path: &str, index: usize) -> serde_json::Value {
+        let args: DecodeArgs = parse(&["decode", path, "coOl"]);
+        let bytes = fs::read(path).unwrap();
+        let png = PngRef::try_from(bytes.as_slice()).unwrap();
+        let messages = secret_pics::messages(&png, "coOl").unwrap();
+        let decoded = decode_message(&png, &messages[index], index, &args, &mut None).unwrap();
+        serde_json::to_value(&decoded).unwrap()
+    }
+
+    #[test]
+    fn test_decode_json_holds_the_message_once() {
+        let envelope = Envelope {
+            filename: Some("notes.md".to_string()),
+            ..Envelope::new(b"# notes".to_vec())
+        };
+        let (_dir, path) = testing_file(&[b"raw text", &envelope.to_bytes().unwrap()]);
+
+        let raw = decoded_json(&path, 0);
+        assert_eq!(raw["data"], "raw text");
+        assert!(raw["envelope"].is_null());
+
+        let json = decoded_json(&path, 1);
+        assert_eq!(json["encoding"], "utf-8");
+        assert_eq!(json["data"], "# notes");
+        assert_eq!(json["envelope"]["filename"], "notes.md");
+        assert!(json["envelope"].get("data").is_none());
+    }
+
+    #[test]
+    fn test_verify_picks_among_several_messages() {
+        let key = SigningKey::generate().unwrap();
+        let signed = |message: &[u8], image_digest| {
+            let envelope = Envelope::new(message.to_vec());
+            let signed = secret_pics::signature::sign(&envelope, &key, image_digest).unwrap();
+            signed.to_bytes().unwrap()
+        };
+        let (_dir, path) = testing_file(&[b"not signed", &signed(b"signed", None)]);
+        let check = |path: &str, args: &[&str]| {
+            let args = [&["verify", path, "coOl"], args].concat();
+            verify(parse(&args), Format::Text)
+        };
+
+        assert!(matches!(check(&path, &[]), Err(Error::NotSigned)));
+        assert!(check(&path, &["--index", "1"]).unwrap());
+        assert!(check(&path, &["--all"]).unwrap());
+        assert!(matches!(
+            check(&path, &["--index", "2"]),
+            Err(Error::MessageNotFound { count: 2, .. })
+        ));
+
+        // a signature bound to another image fails the whole run
+        let (_dir, path) = testing_file(&[&signed(b"one", None), &signed(b"two", Some([0; 32]))]);
+        assert!(!check(&path, &["--all"]).unwrap());
+        assert!(check(&path, &[]).unwrap());
+    }
+
+    #[test]
+    fn test_decode_json_of_a_split_message() {
+        let pieces = secret_pics::split::split(b"hello world this is split", 5).unwrap();
+        let pieces: Vec<&[u8]> = pieces.iter().map(Vec::as_slice).collect();
+        let (_dir, path) = testing_file(&pieces);
+
+        let json = decoded_json(&path, 0);
+        assert_eq!(json["data"], "hello world this is split");
+        assert!(json["envelope"].is_null());
+        assert_eq!(json["type"], "coOl");
+        assert_eq!(json["pieces"], 5);
+        let chunks = json["chunks"].as_array().unwrap();
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[0]["offset"], 8 + 25);
+        assert_eq!(chunks[0]["length"], secret_pics::Piece::HEADER_LEN + 5);
+        assert!(json.get("crc").is_none());
+    }
+
+    #[test]
+    fn test_update_keeps_the_format_and_metadata() {
+        let envelope = Envelope {
+            created_at: Some(1_700_000_000),
+            content_type: Some("text/markdown".to_string()),
+            filename: Some("notes.md".to_string()),
+            ..Envelope::new(b"# notes".to_vec())
+        };
+        let (_dir, path) = testing_file(&[b"raw text", &envelope.to_bytes().unwrap()]);
+
+        update(parse(&["update", &path, "coOl", "new raw text"])).unwrap();
+        update(parse(&[
+            "update",
+            &path,
+            "coOl",
+            "# new notes",
+            "--index",
+            "1",
+        ]))
+        .unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        let png = PngRef::try_from(bytes.as_slice()).unwrap();
+        let messages = secret_pics::messages(&png, "coOl").unwrap();
+        assert_eq!(messages[0][0].data(), b"new raw text");
+        let updated = Envelope::try_from(messages[1][0].data()).unwrap();
+        assert_eq!(updated.payload, b"# new notes");
+        assert_eq!(updated.content_type.as_deref(), Some("text/markdown"));
+        assert_eq!(updated.filename.as_deref(), Some("notes.md"));
+        assert_eq!(updated.created_at, Some(1_700_000_000));
+    }
+
+    #[test]
+    fn test_encode_accepts_the_deprecated_output_file() {
+        let (dir, path) = testing_file(&[]);
+        let secret = dir.path().join("secret.txt");
+        fs::write(&secret, b"from a file").unwrap();
+        let (secret, dir) = (secret.to_str().unwrap(), dir.path().to_str().unwrap());
+        let message = |png: &str| {
+            let bytes = fs::read(png).unwrap();
+            let png = PngRef::try_from(bytes.as_slice()).unwrap();
+            let data = secret_pics::extract_ref(&png, "ruSt").unwrap();
+            Envelope::try_from(data.as_ref()).unwrap().payload
+        };
+
+        let out = format!("{}/message.png", dir);
+        encode(parse(&["encode", &path, "ruSt", "hi", &out])).unwrap();
+        assert_eq!(message(&out), b"hi");
+        let out = format!("{}/file.png", dir);
+        encode(parse(&["encode", &path, "ruSt", "--file", secret, &out])).unwrap();
+        assert_eq!(message(&out), b"from a file");
+
+        let args = ["encode", &path, "ruSt", "--file", secret, "hi", &out];
+        assert!(encode(parse(&args)).is_err());
+        let args = ["encode", &path, "ruSt", "--file", secret, &out, "-o", "-"];
+        assert!(encode(parse(&args)).is_err());
+    }
+
+    #[test]
+    fn test_encode_checks_the_chunk_type() {
+        let (_dir, path) = testing_file(&[]);
+        let before = fs::read(&path).unwrap();
+        for name in ["CoOl", "IDAT"] {
+            assert!(matches!(
+                encode(parse(&["encode", &path, name, "hi"])),
+                Err(Error::CriticalChunkType(_))
+            ));
+            assert_eq!(fs::read(&path).unwrap(), before);
+        }
+
+        let text = ChunkType::from_str("tEXt").unwrap();
+        let warning = chunk_type_warning(text).unwrap().unwrap();
+        assert!(warning.contains("textual data") && warning.contains("teXt"));
+        assert_eq!(
+            chunk_type_warning(ChunkType::from_str("ruSt").unwrap()).unwrap(),
+            None
+        );
+        encode(parse(&["encode", &path, "tEXt", "hi"])).unwrap();
+
+        // --force leaves the check to the one who asked for it
+        encode(parse(&["encode", &path, "CoOl", "hi", "--force"])).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let png = PngRef::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 2 + 2);
+    }
+}
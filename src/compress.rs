@@ -0,0 +1,201 @@
+use crate::envelope::{Envelope, Flags};
+use crate::{Error, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// The algorithm that compressed an envelope's payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// deflate in a zlib stream, always available
+    #[default]
+    Zlib,
+    /// zstandard, needs the `zstd` cargo feature
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// the largest payload [`decompress`] produces unless told otherwise
+    pub const DEFAULT_LIMIT: usize = 64 << 20;
+
+    fn id(&self) -> u8 {
+        match self {
+            Compression::Zlib => 1,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => 2,
+        }
+    }
+
+    /// Tells which algorithm compressed an envelope, `None` when it is not
+    /// compressed
+    pub fn of(envelope: &Envelope) -> Result<Option<Compression>> {
+        if !envelope.flags.contains(Flags::COMPRESSED) {
+            return Ok(None);
+        }
+        match envelope.payload.first() {
+            Some(&id) => Compression::try_from(id).map(Some),
+            None => Err(Error::MalformedEnvelope { offset: 0 }),
+        }
+    }
+}
+
+impl TryFrom<u8> for Compression {
+    type Error = Error;
+
+    fn try_from(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Compression::Zlib),
+            #[cfg(feature = "zstd")]
+            2 => Ok(Compression::Zstd),
+            _ => Err(Error::UnsupportedCompression(id)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Zlib => write!(f, "zlib"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// Compresses the payload of `envelope`, keeping its metadata as it is and
+/// setting the compressed flag. The payload becomes the algorithm's id, 1
+/// for zlib and 2 for zstd, followed by the compressed bytes. Compress
+/// before encrypting, ciphertext does not compress.
+pub fn compress(envelope: &Envelope, compression: Compression) -> Result<Envelope> {
+    let mut payload = vec![compression.id()];
+    match compression {
+        Compression::Zlib => {
+            let mut encoder = ZlibEncoder::new(payload, flate2::Compression::best());
+            encoder.write_all(&envelope.payload)?;
+            payload = encoder.finish()?;
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(payload, 19)?;
+            encoder.write_all(&envelope.payload)?;
+            payload = encoder.finish()?;
+        }
+    }
+    let mut flags = envelope.flags;
+    flags.insert(Flags::COMPRESSED);
+    Ok(Envelope {
+        flags,
+        payload,
+        ..envelope.clone()
+    })
+}
+
+/// Undoes [`compress`], refusing to produce more than `limit` bytes so a
+/// small crafted chunk cannot expand into gigabytes. Envelopes without the
+/// compressed flag are returned as they are.
+pub fn decompress(envelope: &Envelope, limit: usize) -> Result<Envelope> {
+    let compression = match Compression::of(envelope)? {
+        Some(compression) => compression,
+        None => return Ok(envelope.clone()),
+    };
+    let compressed = &envelope.payload[1..];
+    let decoder: Box<dyn Read + '_> = match compression {
+        Compression::Zlib => Box::new(ZlibDecoder::new(compressed)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(zstd::Decoder::new(compressed)?),
+    };
+    // one byte past the limit tells a payload of exactly `limit` bytes from
+    // a larger one
+    let mut payload = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut payload)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => Error::DecompressionFailed,
+            _ => Error::Io(err),
+        })?;
+    if payload.len() > limit {
+        return Err(Error::DecompressedTooLarge { limit });
+    }
+    let mut flags = envelope.flags;
+    flags.remove(Flags::COMPRESSED);
+    Ok(Envelope {
+        flags,
+        payload,
+        ..envelope.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_envelope() -> Envelope {
+        Envelope {
+            content_type: Some("text/plain".to_string()),
+            ..Envelope::new(b"a long message ".repeat(100))
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let envelope = testing_envelope();
+        let compressed = compress(&envelope, Compression::Zlib).unwrap();
+        assert!(compressed.flags.contains(Flags::COMPRESSED));
+        assert_eq!(compressed.content_type, envelope.content_type);
+        assert!(compressed.payload.len() < envelope.payload.len() / 10);
+        assert_eq!(
+            Compression::of(&compressed).unwrap(),
+            Some(Compression::Zlib)
+        );
+        let decompressed = decompress(&compressed, Compression::DEFAULT_LIMIT).unwrap();
+        assert_eq!(decompressed, envelope);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_round_trip() {
+        let envelope = testing_envelope();
+        let compressed = compress(&envelope, Compression::Zstd).unwrap();
+        assert_eq!(compressed.payload[0], 2);
+        let decompressed = decompress(&compressed, Compression::DEFAULT_LIMIT).unwrap();
+        assert_eq!(decompressed, envelope);
+    }
+
+    #[test]
+    fn test_limit() {
+        let envelope = testing_envelope();
+        let compressed = compress(&envelope, Compression::Zlib).unwrap();
+        let exact = envelope.payload.len();
+        assert!(decompress(&compressed, exact).is_ok());
+        assert!(matches!(
+            decompress(&compressed, exact - 1),
+            Err(Error::DecompressedTooLarge { limit }) if limit == exact - 1
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_and_unknown_payloads() {
+        let mut compressed = compress(&testing_envelope(), Compression::Zlib).unwrap();
+        compressed.payload[1] ^= 0xff;
+        assert!(matches!(
+            decompress(&compressed, Compression::DEFAULT_LIMIT),
+            Err(Error::DecompressionFailed)
+        ));
+        compressed.payload[0] = 9;
+        assert!(matches!(
+            decompress(&compressed, Compression::DEFAULT_LIMIT),
+            Err(Error::UnsupportedCompression(9))
+        ));
+    }
+
+    #[test]
+    fn test_plain_envelope_is_returned_as_is() {
+        let envelope = testing_envelope();
+        assert_eq!(Compression::of(&envelope).unwrap(), None);
+        assert_eq!(decompress(&envelope, 0).unwrap(), envelope);
+    }
+}
@@ -0,0 +1,129 @@
+// crc-32 as used by png (ISO 3309 / ITU-T V.42, polynomial 0xedb88320)
+//
+// The lookup tables are built at compile time. Data is processed eight bytes
+// per step with the slicing-by-8 technique: table k maps a byte to its crc
+// contribution when k more zero bytes follow it, so eight independent lookups
+// replace eight dependent ones.
+
+const TABLES: [[u32; 256]; 8] = make_tables();
+
+const fn make_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0_u32; 256]; 8];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        tables[0][n] = c;
+        n += 1;
+    }
+    let mut n = 0;
+    while n < 256 {
+        let mut t = 1;
+        while t < 8 {
+            let previous = tables[t - 1][n];
+            tables[t][n] = (previous >> 8) ^ tables[0][(previous & 0xff) as usize];
+            t += 1;
+        }
+        n += 1;
+    }
+    tables
+}
+
+/// Incremental crc-32 hasher, feeding it a buffer in pieces gives the same
+/// result as [`crc`] on the whole buffer
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { state: 0xffffffff }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        let mut c = self.state;
+        let mut blocks = buf.chunks_exact(8);
+        for block in &mut blocks {
+            let low = c ^ u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+            let high = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+            c = TABLES[7][(low & 0xff) as usize]
+                ^ TABLES[6][((low >> 8) & 0xff) as usize]
+                ^ TABLES[5][((low >> 16) & 0xff) as usize]
+                ^ TABLES[4][(low >> 24) as usize]
+                ^ TABLES[3][(high & 0xff) as usize]
+                ^ TABLES[2][((high >> 8) & 0xff) as usize]
+                ^ TABLES[1][((high >> 16) & 0xff) as usize]
+                ^ TABLES[0][(high >> 24) as usize];
+        }
+        for &b in blocks.remainder() {
+            c = TABLES[0][((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+        }
+        self.state = c;
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.state ^ 0xffffffff
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+/// crc-32 of a whole buffer
+pub fn crc(buf: &[u8]) -> u32 {
+    let mut hasher = Crc32::new();
+    hasher.update(buf);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the original bytewise implementation, kept to check the fast one against
+    fn reference_crc(buf: &[u8]) -> u32 {
+        let mut c = 0xffffffff;
+        for &b in buf {
+            c = TABLES[0][((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+        }
+        c ^ 0xffffffff
+    }
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(crc(b""), 0);
+        assert_eq!(crc(b"123456789"), 0xcbf43926);
+        assert_eq!(crc(b"IEND"), 0xae426082);
+    }
+
+    #[test]
+    fn test_matches_bytewise_crc() {
+        let data: Vec<u8> = (0..1000_u32).map(|i| (i * 7 + i / 3) as u8).collect();
+        for len in 0..data.len() {
+            assert_eq!(crc(&data[..len]), reference_crc(&data[..len]));
+        }
+    }
+
+    #[test]
+    fn test_incremental_updates() {
+        let data: Vec<u8> = (0..100_u8).collect();
+        for split in 0..data.len() {
+            let mut hasher = Crc32::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finish(), crc(&data));
+        }
+    }
+}
@@ -0,0 +1,658 @@
+use crate::envelope::{Envelope, Flags};
+use crate::{Error, Result};
+use aes_gcm::Aes256Gcm;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use x25519_dalek::StaticSecret;
+use zeroize::Zeroizing;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+/// an ephemeral public key and the file key sealed to one recipient
+const STANZA_LEN: usize = 32 + KEY_LEN + 16;
+const STANZA_INFO: &[u8] = b"secret_pics x25519 stanza";
+const PUBLIC_KEY_PREFIX: &str = "sppub:";
+const IDENTITY_PREFIX: &str = "spsec:";
+
+/// Where the key of an encrypted envelope comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource {
+    /// derived from a passphrase with argon2id, see [`encrypt`]
+    Passphrase,
+    /// a random key sealed to each recipient's public key, see [`encrypt_to`]
+    Recipients,
+}
+
+impl KeySource {
+    fn id(&self) -> u8 {
+        match self {
+            KeySource::Passphrase => 1,
+            KeySource::Recipients => 2,
+        }
+    }
+
+    /// Tells how an encrypted envelope was sealed, so the caller knows
+    /// whether to ask for a passphrase or an identity
+    pub fn of(envelope: &Envelope) -> Result<KeySource> {
+        match envelope.payload.get(1) {
+            Some(1) => Ok(KeySource::Passphrase),
+            Some(2) => Ok(KeySource::Recipients),
+            Some(&id) => Err(Error::UnsupportedKeySource(id)),
+            None => Err(Error::MalformedEnvelope {
+                offset: envelope.payload.len(),
+            }),
+        }
+    }
+}
+
+/// The aead used to seal an encrypted envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cipher {
+    #[default]
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl Cipher {
+    fn id(&self) -> u8 {
+        match self {
+            Cipher::ChaCha20Poly1305 => 1,
+            Cipher::Aes256Gcm => 2,
+        }
+    }
+
+    fn seal(&self, key: &[u8], nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let payload = Payload { msg, aad };
+        let sealed = match self {
+            Cipher::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload)
+            }
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(nonce.into(), payload),
+        };
+        // only fails for messages over the aead's length limit of many gigabytes
+        sealed.map_err(|_| Error::DataTooLong { length: msg.len() })
+    }
+
+    fn open(&self, key: &[u8], nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let payload = Payload { msg, aad };
+        let opened = match self {
+            Cipher::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload)
+            }
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+        };
+        opened.map_err(|_| Error::DecryptionFailed)
+    }
+}
+
+impl TryFrom<u8> for Cipher {
+    type Error = Error;
+
+    fn try_from(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Cipher::ChaCha20Poly1305),
+            2 => Ok(Cipher::Aes256Gcm),
+            _ => Err(Error::UnsupportedCipher(id)),
+        }
+    }
+}
+
+/// Argon2id cost parameters, stored with every encrypted envelope so they
+/// can be raised later without breaking old messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// the most a file may ask for, four times the default, so a crafted
+    /// chunk cannot make decoding allocate gigabytes or run for seconds
+    /// before the passphrase is even checked
+    pub const MAX: KdfParams = KdfParams {
+        memory_kib: 4 * 19 * 1024,
+        iterations: 8,
+        parallelism: 4,
+    };
+
+    fn derive_key(&self, passphrase: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+        if self.memory_kib > KdfParams::MAX.memory_kib
+            || self.iterations > KdfParams::MAX.iterations
+            || self.parallelism > KdfParams::MAX.parallelism
+        {
+            return Err(Error::InvalidKdfParams);
+        }
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|_| Error::InvalidKdfParams)?;
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase, salt, key.as_mut())
+            .map_err(|_| Error::InvalidKdfParams)?;
+        Ok(key)
+    }
+}
+
+/// The owasp recommendation for argon2id: 19 MiB, two passes, one lane
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// An x25519 public key that messages can be encrypted to, written as
+/// `sppub:` and the base64url of its 32 bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey([u8; 32]);
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            PUBLIC_KEY_PREFIX,
+            BASE64_URL_SAFE_NO_PAD.encode(self.0)
+        )
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(PublicKey(decode_key(s, PUBLIC_KEY_PREFIX)?))
+    }
+}
+
+/// The x25519 private key of a recipient, written as `spsec:` and the
+/// base64url of its 32 bytes. It is kept out of `Debug` and `Display` so it
+/// cannot be printed by accident, use [`Identity::to_secret_string`].
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    pub fn generate() -> Result<Identity> {
+        let mut bytes = Zeroizing::new([0; 32]);
+        getrandom::getrandom(bytes.as_mut()).map_err(io::Error::from)?;
+        Ok(Identity(StaticSecret::from(*bytes)))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(x25519_dalek::PublicKey::from(&self.0).to_bytes())
+    }
+
+    pub fn to_secret_string(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{}{}",
+            IDENTITY_PREFIX,
+            BASE64_URL_SAFE_NO_PAD.encode(self.0.as_bytes())
+        ))
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Identity").field(&self.public_key()).finish()
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = Zeroizing::new(decode_key(s, IDENTITY_PREFIX)?);
+        Ok(Identity(StaticSecret::from(*bytes)))
+    }
+}
+
+/// Decodes a key written as `prefix` and the base64url of its 32 bytes
+pub(crate) fn decode_key(s: &str, prefix: &str) -> Result<[u8; 32]> {
+    let encoded = s.trim().strip_prefix(prefix).ok_or(Error::InvalidKey)?;
+    let bytes = BASE64_URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|_| Error::InvalidKey)?;
+    bytes.try_into().map_err(|_| Error::InvalidKey)
+}
+
+/// Derives the key that seals the file key for one recipient from the
+/// shared secret and both public keys
+fn stanza_key(shared: &[u8], ephemeral: &[u8], recipient: &PublicKey) -> Zeroizing<[u8; KEY_LEN]> {
+    let mut salt = [0; 64];
+    salt[..32].copy_from_slice(ephemeral);
+    salt[32..].copy_from_slice(&recipient.0);
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(STANZA_INFO, key.as_mut())
+        .expect("32 bytes is a valid hkdf-sha256 output length");
+    key
+}
+
+/// Seals `envelope` under `key` after `header`, returning the encrypted
+/// envelope whose payload is the header, a fresh nonce and the ciphertext.
+/// The header and nonce are authenticated with the ciphertext.
+fn seal(envelope: &Envelope, cipher: Cipher, key: &[u8], mut header: Vec<u8>) -> Result<Envelope> {
+    let mut nonce = [0; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(io::Error::from)?;
+    header.extend_from_slice(&nonce);
+    let plaintext = Zeroizing::new(envelope.to_bytes()?);
+    let sealed = cipher.seal(key, &nonce, &plaintext, &header)?;
+
+    let mut flags = Flags::default();
+    flags.insert(Flags::ENCRYPTED);
+    header.extend_from_slice(&sealed);
+    Ok(Envelope {
+        flags,
+        payload: header,
+        ..Envelope::default()
+    })
+}
+
+/// Opens the ciphertext that follows the nonce at `nonce_at` in `bytes`
+fn open(bytes: &[u8], cipher: Cipher, key: &[u8], nonce_at: usize) -> Result<Envelope> {
+    let header_len = nonce_at + NONCE_LEN;
+    let plaintext = Zeroizing::new(cipher.open(
+        key,
+        &bytes[nonce_at..header_len],
+        &bytes[header_len..],
+        &bytes[..header_len],
+    )?);
+    Envelope::try_from(plaintext.as_slice())
+}
+
+/// Checks that an encrypted payload is at least `len` bytes long
+fn check_len(bytes: &[u8], len: usize) -> Result<()> {
+    match bytes.len() < len {
+        true => Err(Error::MalformedEnvelope {
+            offset: bytes.len(),
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Seals `envelope`, metadata included, with a key derived from
+/// `passphrase`. The result is a new envelope with only the encrypted flag
+/// set, whose payload is laid out as:
+///
+/// | bytes | field |
+/// |-------|-------|
+/// | 1 | cipher, 1 for chacha20-poly1305 and 2 for aes-256-gcm |
+/// | 1 | key source, 1 for a passphrase |
+/// | 12 | argon2id memory in KiB, iterations and parallelism, u32 big endian |
+/// | 16 | salt |
+/// | 12 | nonce |
+/// | rest | the sealed inner envelope and its tag |
+///
+/// Everything before the sealed envelope is authenticated with it.
+pub fn encrypt(
+    envelope: &Envelope,
+    passphrase: &[u8],
+    cipher: Cipher,
+    params: KdfParams,
+) -> Result<Envelope> {
+    let mut salt = [0; SALT_LEN];
+    getrandom::getrandom(&mut salt).map_err(io::Error::from)?;
+    let mut header = vec![cipher.id(), KeySource::Passphrase.id()];
+    header.extend_from_slice(&params.memory_kib.to_be_bytes());
+    header.extend_from_slice(&params.iterations.to_be_bytes());
+    header.extend_from_slice(&params.parallelism.to_be_bytes());
+    header.extend_from_slice(&salt);
+    let key = params.derive_key(passphrase, &salt)?;
+    seal(envelope, cipher, key.as_ref(), header)
+}
+
+/// Opens an envelope sealed by [`encrypt`], returning the inner envelope.
+/// Envelopes without the encrypted flag are returned as they are.
+pub fn decrypt(envelope: &Envelope, passphrase: &[u8]) -> Result<Envelope> {
+    if !envelope.flags.contains(Flags::ENCRYPTED) {
+        return Ok(envelope.clone());
+    }
+    let bytes = &envelope.payload;
+    let nonce_at = 2 + 12 + SALT_LEN;
+    check_len(bytes, nonce_at + NONCE_LEN)?;
+    let cipher = Cipher::try_from(bytes[0])?;
+    if KeySource::of(envelope)? != KeySource::Passphrase {
+        return Err(Error::UnsupportedKeySource(bytes[1]));
+    }
+    let read_u32 =
+        |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let params = KdfParams {
+        memory_kib: read_u32(2),
+        iterations: read_u32(6),
+        parallelism: read_u32(10),
+    };
+    let key = params.derive_key(passphrase, &bytes[14..nonce_at])?;
+    open(bytes, cipher, key.as_ref(), nonce_at)
+}
+
+/// Seals `envelope`, metadata included, so that any one of `recipients` can
+/// open it with their [`Identity`]. A random key encrypts the envelope and
+/// is itself sealed to each recipient in a stanza, using x25519 with a fresh
+/// ephemeral key and hkdf-sha256. The payload is laid out as:
+///
+/// | bytes | field |
+/// |-------|-------|
+/// | 1 | cipher, 1 for chacha20-poly1305 and 2 for aes-256-gcm |
+/// | 1 | key source, 2 for recipients |
+/// | 1 | number of stanzas |
+/// | 80 each | ephemeral public key, then the key sealed with chacha20-poly1305 |
+/// | 12 | nonce |
+/// | rest | the sealed inner envelope and its tag |
+///
+/// Everything before the sealed envelope is authenticated with it.
+pub fn encrypt_to(
+    envelope: &Envelope,
+    recipients: &[PublicKey],
+    cipher: Cipher,
+) -> Result<Envelope> {
+    if recipients.is_empty() {
+        return Err(Error::NoRecipients);
+    }
+    let count = u8::try_from(recipients.len()).map_err(|_| Error::FieldTooLong {
+        field: "recipient list",
+        length: recipients.len(),
+    })?;
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    getrandom::getrandom(key.as_mut()).map_err(io::Error::from)?;
+    let mut header = vec![cipher.id(), KeySource::Recipients.id(), count];
+    for recipient in recipients {
+        let ephemeral = Identity::generate()?;
+        let ephemeral_public = ephemeral.public_key();
+        let shared = ephemeral
+            .0
+            .diffie_hellman(&x25519_dalek::PublicKey::from(recipient.0));
+        // a low order key gives a shared secret anyone can compute
+        if !shared.was_contributory() {
+            return Err(Error::InvalidKey);
+        }
+        let stanza_key = stanza_key(shared.as_bytes(), &ephemeral_public.0, recipient);
+        // every stanza key is used once, so a fixed nonce is safe
+        let wrapped = Cipher::ChaCha20Poly1305.seal(
+            stanza_key.as_ref(),
+            &[0; NONCE_LEN],
+            key.as_ref(),
+            &[],
+        )?;
+        header.extend_from_slice(&ephemeral_public.0);
+        header.extend_from_slice(&wrapped);
+    }
+    seal(envelope, cipher, key.as_ref(), header)
+}
+
+/// Opens an envelope sealed by [`encrypt_to`] with the first of
+/// `identities` that one of its stanzas was sealed to. Envelopes without the
+/// encrypted flag are returned as they are.
+pub fn decrypt_with(envelope: &Envelope, identities: &[Identity]) -> Result<Envelope> {
+    if !envelope.flags.contains(Flags::ENCRYPTED) {
+        return Ok(envelope.clone());
+    }
+    let bytes = &envelope.payload;
+    check_len(bytes, 3)?;
+    let cipher = Cipher::try_from(bytes[0])?;
+    if KeySource::of(envelope)? != KeySource::Recipients {
+        return Err(Error::UnsupportedKeySource(bytes[1]));
+    }
+    let nonce_at = 3 + bytes[2] as usize * STANZA_LEN;
+    check_len(bytes, nonce_at + NONCE_LEN)?;
+    for stanza in bytes[3..nonce_at].chunks_exact(STANZA_LEN) {
+        let (ephemeral, wrapped) = stanza.split_at(32);
+        let ephemeral: [u8; 32] = ephemeral.try_into().unwrap();
+        for identity in identities {
+            let shared = identity
+                .0
+                .diffie_hellman(&x25519_dalek::PublicKey::from(ephemeral));
+            if !shared.was_contributory() {
+                continue;
+            }
+            let stanza_key = stanza_key(shared.as_bytes(), &ephemeral, &identity.public_key());
+            let key = match Cipher::ChaCha20Poly1305.open(
+                stanza_key.as_ref(),
+                &[0; NONCE_LEN],
+                wrapped,
+                &[],
+            ) {
+                Ok(key) => Zeroizing::new(key),
+                // sealed to someone else
+                Err(_) => continue,
+            };
+            return open(bytes, cipher, &key, nonce_at);
+        }
+    }
+    Err(Error::NoMatchingIdentity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cheap parameters so the tests do not spend seconds in argon2
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn testing_envelope() -> Envelope {
+        Envelope {
+            filename: Some("secret.txt".to_string()),
+            ..Envelope::new(b"meet at noon".to_vec())
+        }
+    }
+
+    #[test]
+    fn test_round_trip_both_ciphers() {
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let sealed = encrypt(&testing_envelope(), b"hunter2", cipher, TEST_PARAMS).unwrap();
+            assert!(sealed.flags.contains(Flags::ENCRYPTED));
+            assert_eq!(sealed.filename, None);
+            assert_eq!(sealed.payload[0], cipher.id());
+            assert_eq!(decrypt(&sealed, b"hunter2").unwrap(), testing_envelope());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_through_bytes() {
+        let sealed = encrypt(&testing_envelope(), b"pw", Cipher::default(), TEST_PARAMS).unwrap();
+        let parsed = Envelope::try_from(sealed.to_bytes().unwrap().as_slice()).unwrap();
+        assert_eq!(decrypt(&parsed, b"pw").unwrap(), testing_envelope());
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let sealed = encrypt(
+            &testing_envelope(),
+            b"right",
+            Cipher::default(),
+            TEST_PARAMS,
+        )
+        .unwrap();
+        assert!(matches!(
+            decrypt(&sealed, b"wrong"),
+            Err(Error::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let sealed = encrypt(&testing_envelope(), b"pw", Cipher::default(), TEST_PARAMS).unwrap();
+        // the header is authenticated as well as the ciphertext
+        for i in [0, 14, 30, sealed.payload.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered.payload[i] ^= 1;
+            assert!(decrypt(&tampered, b"pw").is_err(), "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn test_hostile_kdf_params_are_refused() {
+        let mut sealed =
+            encrypt(&testing_envelope(), b"pw", Cipher::default(), TEST_PARAMS).unwrap();
+        sealed.payload[2..6].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            decrypt(&sealed, b"pw"),
+            Err(Error::InvalidKdfParams)
+        ));
+        // a gigabyte is refused up front, before argon2 allocates any of it
+        sealed.payload[2..6].copy_from_slice(&(1_u32 << 20).to_be_bytes());
+        assert!(matches!(
+            decrypt(&sealed, b"pw"),
+            Err(Error::InvalidKdfParams)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_and_unknown_headers() {
+        let sealed = encrypt(&testing_envelope(), b"pw", Cipher::default(), TEST_PARAMS).unwrap();
+        let mut truncated = sealed.clone();
+        truncated.payload.truncate(20);
+        assert!(matches!(
+            decrypt(&truncated, b"pw"),
+            Err(Error::MalformedEnvelope { .. })
+        ));
+        let mut unknown = sealed;
+        unknown.payload[0] = 9;
+        assert!(matches!(
+            decrypt(&unknown, b"pw"),
+            Err(Error::UnsupportedCipher(9))
+        ));
+    }
+
+    #[test]
+    fn test_plain_envelope_is_returned_as_is() {
+        assert_eq!(
+            decrypt(&testing_envelope(), b"pw").unwrap(),
+            testing_envelope()
+        );
+        assert_eq!(
+            decrypt_with(&testing_envelope(), &[]).unwrap(),
+            testing_envelope()
+        );
+    }
+
+    #[test]
+    fn test_every_recipient_can_decrypt() {
+        let alice = Identity::generate().unwrap();
+        let bob = Identity::generate().unwrap();
+        let recipients = [alice.public_key(), bob.public_key()];
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let sealed = encrypt_to(&testing_envelope(), &recipients, cipher).unwrap();
+            assert_eq!(KeySource::of(&sealed).unwrap(), KeySource::Recipients);
+            for identity in [&alice, &bob] {
+                let identity = Identity::from_str(&identity.to_secret_string()).unwrap();
+                assert_eq!(
+                    decrypt_with(&sealed, &[identity]).unwrap(),
+                    testing_envelope()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_other_identity_cannot_decrypt() {
+        let alice = Identity::generate().unwrap();
+        let eve = Identity::generate().unwrap();
+        let sealed = encrypt_to(
+            &testing_envelope(),
+            &[alice.public_key()],
+            Cipher::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            decrypt_with(&sealed, &[eve]),
+            Err(Error::NoMatchingIdentity)
+        ));
+        assert!(matches!(
+            decrypt(&sealed, b"pw"),
+            Err(Error::UnsupportedKeySource(2))
+        ));
+    }
+
+    #[test]
+    fn test_recipient_tampering_is_detected() {
+        let alice = Identity::generate().unwrap();
+        let sealed = encrypt_to(
+            &testing_envelope(),
+            &[alice.public_key()],
+            Cipher::default(),
+        )
+        .unwrap();
+        // a changed stanza no longer opens, a changed body fails authentication
+        for i in [3, 40, sealed.payload.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered.payload[i] ^= 1;
+            assert!(decrypt_with(
+                &tampered,
+                &[Identity::from_str(&alice.to_secret_string()).unwrap()]
+            )
+            .is_err());
+        }
+        let mut truncated = sealed;
+        truncated.payload.truncate(50);
+        assert!(matches!(
+            decrypt_with(&truncated, &[alice]),
+            Err(Error::MalformedEnvelope { .. })
+        ));
+    }
+
+    #[test]
+    fn test_key_strings() {
+        let identity = Identity::generate().unwrap();
+        let public = identity.public_key();
+        let text = public.to_string();
+        assert!(text.starts_with("sppub:"));
+        assert_eq!(PublicKey::from_str(&text).unwrap(), public);
+        assert_eq!(
+            Identity::from_str(&identity.to_secret_string())
+                .unwrap()
+                .public_key(),
+            public
+        );
+        assert!(!format!("{:?}", identity).contains(identity.to_secret_string().as_str()));
+        for bad in ["", "sppub:", "spsec:AAAA", "nope", &text[..text.len() - 1]] {
+            assert!(matches!(PublicKey::from_str(bad), Err(Error::InvalidKey)));
+        }
+        assert!(matches!(Identity::from_str(&text), Err(Error::InvalidKey)));
+    }
+
+    #[test]
+    fn test_no_recipients() {
+        assert!(matches!(
+            encrypt_to(&testing_envelope(), &[], Cipher::default()),
+            Err(Error::NoRecipients)
+        ));
+        let recipients = vec![Identity::generate().unwrap().public_key(); 256];
+        assert!(matches!(
+            encrypt_to(&testing_envelope(), &recipients, Cipher::default()),
+            Err(Error::FieldTooLong { length: 256, .. })
+        ));
+    }
+
+    #[test]
+    fn test_low_order_keys_are_refused() {
+        // points of order two and four
+        let mut order_four = [0; 32];
+        order_four[0] = 1;
+        for key in [[0; 32], order_four] {
+            assert!(matches!(
+                encrypt_to(&testing_envelope(), &[PublicKey(key)], Cipher::default()),
+                Err(Error::InvalidKey)
+            ));
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use crate::chunk::serialize_data;
+use crate::{Error, Result};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::convert::TryFrom;
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Flag bits saying how an [`Envelope`]'s payload was transformed before it
+/// was stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags(u8);
+
+impl Flags {
+    pub const COMPRESSED: Flags = Flags(1);
+    pub const ENCRYPTED: Flags = Flags(1 << 1);
+    pub const SIGNED: Flags = Flags(1 << 2);
+    /// every flag this version knows about, any other bit is refused
+    const KNOWN: u8 = Flags::COMPRESSED.0 | Flags::ENCRYPTED.0 | Flags::SIGNED.0;
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(&self, flags: Flags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    pub fn insert(&mut self, flags: Flags) {
+        self.0 |= flags.0;
+    }
+
+    pub fn remove(&mut self, flags: Flags) {
+        self.0 &= !flags.0;
+    }
+}
+
+impl TryFrom<u8> for Flags {
+    type Error = Error;
+
+    fn try_from(bits: u8) -> Result<Self> {
+        match bits & !Flags::KNOWN {
+            0 => Ok(Flags(bits)),
+            unknown => Err(Error::UnknownEnvelopeFlags(unknown)),
+        }
+    }
+}
+
+impl Serialize for Flags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("compressed", &self.contains(Flags::COMPRESSED))?;
+        map.serialize_entry("encrypted", &self.contains(Flags::ENCRYPTED))?;
+        map.serialize_entry("signed", &self.contains(Flags::SIGNED))?;
+        map.end()
+    }
+}
+
+/// A message together with what is known about it, stored as the data of a
+/// secret chunk so a reader can tell our chunks from another tool's.
+///
+/// The layout, all integers big endian:
+///
+/// | bytes | field |
+/// |-------|-------|
+/// | 4 | magic, `SPMS` |
+/// | 1 | format version, [`Envelope::VERSION`] |
+/// | 1 | [`Flags`] |
+/// | 8 | created at, seconds since the unix epoch, 0 when unknown |
+/// | 2 + n | content type, utf-8, empty when unknown |
+/// | 2 + n | original file name, utf-8, empty when unknown |
+/// | rest | payload |
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Envelope {
+    pub flags: Flags,
+    /// seconds since the unix epoch
+    pub created_at: Option<u64>,
+    /// mime type of the payload before any compression or encryption
+    pub content_type: Option<String>,
+    /// name of the file the payload was read from
+    pub filename: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub const MAGIC: [u8; 4] = *b"SPMS";
+    pub const VERSION: u8 = 1;
+
+    /// Wraps `payload` with no metadata
+    pub fn new(payload: Vec<u8>) -> Envelope {
+        Envelope {
+            payload,
+            ..Envelope::default()
+        }
+    }
+
+    /// Wraps `payload` stamped with the current time
+    pub fn now(payload: Vec<u8>) -> Envelope {
+        Envelope {
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|elapsed| elapsed.as_secs()),
+            ..Envelope::new(payload)
+        }
+    }
+
+    /// Whether `bytes` start with the envelope magic, so chunks written
+    /// without an envelope can still be read as raw messages
+    pub fn is_envelope(bytes: &[u8]) -> bool {
+        bytes.starts_with(&Envelope::MAGIC)
+    }
+
+    /// Encodes the envelope, or fails if the content type or file name is
+    /// longer than 65535 bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.payload.len() + 64);
+        bytes.extend_from_slice(&Envelope::MAGIC);
+        bytes.push(Envelope::VERSION);
+        bytes.push(self.flags.bits());
+        bytes.extend_from_slice(&self.created_at.unwrap_or(0).to_be_bytes());
+        write_field(&mut bytes, "content type", self.content_type.as_deref())?;
+        write_field(&mut bytes, "file name", self.filename.as_deref())?;
+        bytes.extend_from_slice(&self.payload);
+        Ok(bytes)
+    }
+}
+
+fn write_field(bytes: &mut Vec<u8>, field: &'static str, value: Option<&str>) -> Result<()> {
+    let value = value.unwrap_or("");
+    let length = u16::try_from(value.len()).map_err(|_| Error::FieldTooLong {
+        field,
+        length: value.len(),
+    })?;
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// Reads the length prefixed string at `offset`, returning it and the offset
+/// after it
+fn read_field(bytes: &[u8], offset: usize) -> Result<(Option<String>, usize)> {
+    let length: [u8; 2] = bytes
+        .get(offset..offset + 2)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(Error::MalformedEnvelope { offset })?;
+    let end = offset + 2 + u16::from_be_bytes(length) as usize;
+    let value = bytes
+        .get(offset + 2..end)
+        .and_then(|value| str::from_utf8(value).ok())
+        .ok_or(Error::MalformedEnvelope { offset })?;
+    let value = (!value.is_empty()).then(|| value.to_string());
+    Ok((value, end))
+}
+
+impl TryFrom<&[u8]> for Envelope {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        if !Envelope::is_envelope(bytes) {
+            return Err(Error::NotAnEnvelope);
+        }
+        if bytes.len() < 14 {
+            return Err(Error::MalformedEnvelope {
+                offset: bytes.len(),
+            });
+        }
+        if bytes[4] != Envelope::VERSION {
+            return Err(Error::UnsupportedEnvelopeVersion(bytes[4]));
+        }
+        let flags = Flags::try_from(bytes[5])?;
+        let created_at = u64::from_be_bytes(bytes[6..14].try_into().unwrap());
+        let (content_type, offset) = read_field(bytes, 14)?;
+        let (filename, offset) = read_field(bytes, offset)?;
+        Ok(Envelope {
+            flags,
+            created_at: (created_at != 0).then_some(created_at),
+            content_type,
+            filename,
+            payload: bytes[offset..].to_vec(),
+        })
+    }
+}
+
+/// Serialized as its metadata plus the payload's `encoding` and `data`, like
+/// a [`Chunk`](crate::Chunk)
+impl Serialize for Envelope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("version", &Envelope::VERSION)?;
+        map.serialize_entry("flags", &self.flags)?;
+        map.serialize_entry("created_at", &self.created_at)?;
+        map.serialize_entry("content_type", &self.content_type)?;
+        map.serialize_entry("filename", &self.filename)?;
+        serialize_data(&mut map, &self.payload)?;
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_envelope() -> Envelope {
+        Envelope {
+            flags: Flags::default(),
+            created_at: Some(1_700_000_000),
+            content_type: Some("text/plain".to_string()),
+            filename: Some("note.txt".to_string()),
+            payload: b"hello".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let envelope = testing_envelope();
+        let bytes = envelope.to_bytes().unwrap();
+        assert!(Envelope::is_envelope(&bytes));
+        assert_eq!(Envelope::try_from(bytes.as_slice()).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_empty_metadata_round_trips_as_none() {
+        let envelope = Envelope::new(b"hello".to_vec());
+        let bytes = envelope.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 4 + 1 + 1 + 8 + 2 + 2 + 5);
+        assert_eq!(Envelope::try_from(bytes.as_slice()).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_raw_message_is_not_an_envelope() {
+        assert!(matches!(
+            Envelope::try_from(b"just a message".as_ref()),
+            Err(Error::NotAnEnvelope)
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_version_and_flags() {
+        let mut bytes = testing_envelope().to_bytes().unwrap();
+        bytes[4] = 2;
+        assert!(matches!(
+            Envelope::try_from(bytes.as_slice()),
+            Err(Error::UnsupportedEnvelopeVersion(2))
+        ));
+        bytes[4] = Envelope::VERSION;
+        bytes[5] = 0x87;
+        assert!(matches!(
+            Envelope::try_from(bytes.as_slice()),
+            Err(Error::UnknownEnvelopeFlags(0x80))
+        ));
+    }
+
+    #[test]
+    fn test_every_truncation_is_an_error() {
+        let bytes = testing_envelope().to_bytes().unwrap();
+        // everything up to the end of the file name is required
+        for end in 4..bytes.len() - 5 {
+            assert!(
+                Envelope::try_from(&bytes[..end]).is_err(),
+                "truncated at {}",
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn test_field_too_long() {
+        let mut envelope = testing_envelope();
+        envelope.filename = Some("a".repeat(70_000));
+        assert!(matches!(
+            envelope.to_bytes(),
+            Err(Error::FieldTooLong {
+                field: "file name",
+                length: 70_000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut flags = Flags::default();
+        flags.insert(Flags::ENCRYPTED);
+        assert!(flags.contains(Flags::ENCRYPTED));
+        assert!(!flags.contains(Flags::COMPRESSED));
+        flags.remove(Flags::ENCRYPTED);
+        assert_eq!(flags, Flags::default());
+    }
+
+    #[test]
+    fn test_serialize_envelope() {
+        let json = serde_json::to_value(testing_envelope()).unwrap();
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["flags"]["encrypted"], false);
+        assert_eq!(json["created_at"], 1_700_000_000);
+        assert_eq!(json["filename"], "note.txt");
+        assert_eq!(json["data"], "hello");
+    }
+}
@@ -0,0 +1,334 @@
+use crate::chunk_type::ChunkType;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::fmt;
+use std::io;
+use std::str;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong while reading, editing or writing a png
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// the first eight bytes are not the png signature
+    BadSignature,
+    /// the crc stored after a chunk does not match its type and data,
+    /// `expected` is the crc computed from the chunk and `found` the stored one
+    CrcMismatch {
+        chunk_type: ChunkType,
+        offset: usize,
+        expected: u32,
+        found: u32,
+    },
+    /// the input ended before the structure starting at `offset` was complete
+    Truncated {
+        offset: usize,
+    },
+    /// the chunk at `offset` declares more data than the input has left
+    ChunkOverrun {
+        offset: usize,
+        length: u32,
+        available: usize,
+    },
+    /// the chunk at `offset` declares a length over the 2^31 - 1 spec limit
+    LengthTooLarge {
+        offset: usize,
+        length: u32,
+    },
+    /// chunk data of `length` bytes is over the 2^31 - 1 spec limit
+    DataTooLong {
+        length: usize,
+    },
+    /// the input ended at `offset` without an IEND chunk
+    MissingIend {
+        offset: usize,
+    },
+    /// bytes were left over at `offset` after the end of a chunk
+    TrailingData {
+        offset: usize,
+    },
+    /// no chunk with this type exists in the png
+    ChunkNotFound(ChunkType),
+    /// there are only `count` messages of this type, none at `index`
+    MessageNotFound {
+        chunk_type: ChunkType,
+        index: usize,
+        count: usize,
+    },
+    /// a chunk type that is not four ascii letters
+    InvalidChunkType,
+    /// a chunk type whose third letter, the reserved bit, is lowercase
+    ReservedBitInvalid(ChunkType),
+    /// a message under a critical type would make viewers reject the image
+    CriticalChunkType(ChunkType),
+    /// chunk data was expected to be text but is not valid utf-8
+    InvalidUtf8(str::Utf8Error),
+    /// chunk data does not start with the envelope magic
+    NotAnEnvelope,
+    /// the envelope was written by a newer format version
+    UnsupportedEnvelopeVersion(u8),
+    /// the envelope sets flag bits this version does not know about
+    UnknownEnvelopeFlags(u8),
+    /// the envelope ends or has invalid utf-8 in the field at `offset`
+    MalformedEnvelope {
+        offset: usize,
+    },
+    /// an envelope field is over the 65535 byte limit
+    FieldTooLong {
+        field: &'static str,
+        length: usize,
+    },
+    /// the encrypted envelope uses a cipher this version does not know
+    UnsupportedCipher(u8),
+    /// the encrypted envelope's key comes from a source this version does not know
+    UnsupportedKeySource(u8),
+    /// argon2 parameters that are out of range or over [`KdfParams::MAX`](crate::KdfParams::MAX)
+    InvalidKdfParams,
+    /// the key is wrong or the encrypted message was changed
+    DecryptionFailed,
+    /// the message was not encrypted to any of the given identities
+    NoMatchingIdentity,
+    /// a message encrypted to public keys needs at least one of them
+    NoRecipients,
+    /// a public key or identity that is not its prefix and 32 bytes of base64url
+    InvalidKey,
+    /// the message has no signature to verify
+    NotSigned,
+    /// the signature does not match the message, or it binds a different image
+    SignatureFailed,
+    /// the message is signed with a scheme this version does not know
+    UnsupportedSignatureScheme(u8),
+    /// the message is compressed with an algorithm this build does not know
+    UnsupportedCompression(u8),
+    /// the compressed message is corrupt
+    DecompressionFailed,
+    /// the message decompresses to more than `limit` bytes
+    DecompressedTooLarge {
+        limit: usize,
+    },
+    /// chunk data does not start with the piece magic
+    NotAPiece,
+    /// a piece of a split message is too short or numbered past its total
+    MalformedPiece,
+    /// a message cannot be split into pieces of zero bytes
+    InvalidPieceSize,
+    /// two chunks hold the same piece of a split message
+    DuplicatePiece {
+        index: u32,
+    },
+    /// the piece at `index` of a message split in `total` pieces is missing
+    MissingPiece {
+        index: u32,
+        total: u32,
+    },
+    Io(io::Error),
+}
+
+impl Error {
+    /// A stable snake_case name for the variant, for scripts that need to
+    /// tell errors apart without parsing the message
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::BadSignature => "bad_signature",
+            Error::CrcMismatch { .. } => "crc_mismatch",
+            Error::Truncated { .. } => "truncated",
+            Error::ChunkOverrun { .. } => "chunk_overrun",
+            Error::LengthTooLarge { .. } => "length_too_large",
+            Error::DataTooLong { .. } => "data_too_long",
+            Error::MissingIend { .. } => "missing_iend",
+            Error::TrailingData { .. } => "trailing_data",
+            Error::ChunkNotFound(_) => "chunk_not_found",
+            Error::MessageNotFound { .. } => "message_not_found",
+            Error::InvalidChunkType => "invalid_chunk_type",
+            Error::ReservedBitInvalid(_) => "reserved_bit_invalid",
+            Error::CriticalChunkType(_) => "critical_chunk_type",
+            Error::InvalidUtf8(_) => "invalid_utf8",
+            Error::NotAnEnvelope => "not_an_envelope",
+            Error::UnsupportedEnvelopeVersion(_) => "unsupported_envelope_version",
+            Error::UnknownEnvelopeFlags(_) => "unknown_envelope_flags",
+            Error::MalformedEnvelope { .. } => "malformed_envelope",
+            Error::FieldTooLong { .. } => "field_too_long",
+            Error::UnsupportedCipher(_) => "unsupported_cipher",
+            Error::UnsupportedKeySource(_) => "unsupported_key_source",
+            Error::InvalidKdfParams => "invalid_kdf_params",
+            Error::DecryptionFailed => "decryption_failed",
+            Error::NoMatchingIdentity => "no_matching_identity",
+            Error::NoRecipients => "no_recipients",
+            Error::InvalidKey => "invalid_key",
+            Error::NotSigned => "not_signed",
+            Error::SignatureFailed => "signature_failed",
+            Error::UnsupportedSignatureScheme(_) => "unsupported_signature_scheme",
+            Error::UnsupportedCompression(_) => "unsupported_compression",
+            Error::DecompressionFailed => "decompression_failed",
+            Error::DecompressedTooLarge { .. } => "decompressed_too_large",
+            Error::NotAPiece => "not_a_piece",
+            Error::MalformedPiece => "malformed_piece",
+            Error::InvalidPieceSize => "invalid_piece_size",
+            Error::DuplicatePiece { .. } => "duplicate_piece",
+            Error::MissingPiece { .. } => "missing_piece",
+            Error::Io(_) => "io",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadSignature => write!(f, "invalid png: bad signature"),
+            Error::CrcMismatch {
+                chunk_type,
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "crc mismatch in {} chunk at byte {}: expected {:#010x}, found {:#010x}",
+                chunk_type, offset, expected, found
+            ),
+            Error::Truncated { offset } => write!(f, "invalid png: truncated at byte {}", offset),
+            Error::ChunkOverrun {
+                offset,
+                length,
+                available,
+            } => write!(
+                f,
+                "invalid png: chunk at byte {} declares {} bytes of data but only {} remain",
+                offset, length, available
+            ),
+            Error::LengthTooLarge { offset, length } => write!(
+                f,
+                "invalid png: chunk at byte {} declares {} bytes of data, over the 2^31 - 1 limit",
+                offset, length
+            ),
+            Error::DataTooLong { length } => write!(
+                f,
+                "chunk data of {} bytes is over the 2^31 - 1 limit",
+                length
+            ),
+            Error::MissingIend { offset } => {
+                write!(f, "invalid png: no IEND chunk before byte {}", offset)
+            }
+            Error::TrailingData { offset } => {
+                write!(f, "invalid chunk: unexpected bytes after byte {}", offset)
+            }
+            Error::ChunkNotFound(chunk_type) => {
+                write!(f, "chunk not found: {}", chunk_type)
+            }
+            Error::MessageNotFound {
+                chunk_type,
+                index,
+                count,
+            } => write!(
+                f,
+                "no {} message at index {}, there are only {}",
+                chunk_type, index, count
+            ),
+            Error::InvalidChunkType => write!(f, "invalid chunk type"),
+            Error::ReservedBitInvalid(chunk_type) => write!(
+                f,
+                "{} is not a valid chunk type, its third letter sets the reserved bit and must be uppercase",
+                chunk_type
+            ),
+            Error::CriticalChunkType(chunk_type) => match chunk_type.registered_name() {
+                Some(name) => write!(
+                    f,
+                    "{} is the critical {} chunk, a message under it would corrupt the image",
+                    chunk_type, name
+                ),
+                None => write!(
+                    f,
+                    "{} is a critical chunk type, viewers reject images with critical chunks they do not know",
+                    chunk_type
+                ),
+            },
+            Error::InvalidUtf8(err) => write!(f, "message is not valid utf-8: {}", err),
+            Error::NotAnEnvelope => write!(f, "chunk data is not a message envelope"),
+            Error::UnsupportedEnvelopeVersion(version) => {
+                write!(f, "unsupported message envelope version {}", version)
+            }
+            Error::UnknownEnvelopeFlags(flags) => {
+                write!(f, "message envelope has unknown flags {:#04x}", flags)
+            }
+            Error::MalformedEnvelope { offset } => {
+                write!(f, "invalid message envelope: bad field at byte {}", offset)
+            }
+            Error::FieldTooLong { field, length } => write!(
+                f,
+                "message {} of {} bytes is over the 65535 byte limit",
+                field, length
+            ),
+            Error::UnsupportedCipher(id) => write!(f, "unsupported cipher {}", id),
+            Error::UnsupportedKeySource(id) => write!(f, "unsupported key source {}", id),
+            Error::InvalidKdfParams => write!(f, "invalid or too costly key derivation parameters"),
+            Error::DecryptionFailed => write!(
+                f,
+                "decryption failed: wrong passphrase or the message was changed"
+            ),
+            Error::NoMatchingIdentity => {
+                write!(
+                    f,
+                    "the message was not encrypted to any of the given identities"
+                )
+            }
+            Error::NoRecipients => write!(f, "no recipients to encrypt to"),
+            Error::InvalidKey => write!(f, "invalid public key or identity"),
+            Error::NotSigned => write!(f, "the message is not signed"),
+            Error::SignatureFailed => write!(f, "the signature check failed"),
+            Error::UnsupportedSignatureScheme(id) => {
+                write!(f, "unsupported signature scheme {}", id)
+            }
+            Error::UnsupportedCompression(id) => write!(f, "unsupported compression {}", id),
+            Error::DecompressionFailed => write!(f, "the compressed message is corrupt"),
+            Error::DecompressedTooLarge { limit } => write!(
+                f,
+                "the message decompresses to more than the {} byte limit",
+                limit
+            ),
+            Error::NotAPiece => write!(f, "chunk data is not a piece of a split message"),
+            Error::MalformedPiece => write!(f, "invalid piece of a split message"),
+            Error::InvalidPieceSize => write!(f, "pieces must hold at least one byte"),
+            Error::DuplicatePiece { index } => {
+                write!(f, "piece {} of the split message appears twice", index + 1)
+            }
+            Error::MissingPiece { index, total } => write!(
+                f,
+                "piece {} of {} of the split message is missing",
+                index + 1,
+                total
+            ),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUtf8(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Serialized as its [`kind`](Error::kind) and display message
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("message", &self.to_string())?;
+        map.end()
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<str::Utf8Error> for Error {
+    fn from(err: str::Utf8Error) -> Self {
+        Error::InvalidUtf8(err)
+    }
+}
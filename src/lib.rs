@@ -0,0 +1,759 @@
+//! Hide, read and remove secret messages inside PNG files.
+//!
+//! A message is stored as the data of an extra chunk whose type acts as the
+//! key for finding it again. [`embed`], [`extract`] and [`remove`] work on an
+//! already loaded [`Png`]; reading and writing files is left to the caller.
+pub mod borrowed;
+pub mod chunk;
+pub mod chunk_type;
+pub mod compress;
+pub mod crc;
+pub mod crypto;
+pub mod envelope;
+pub mod error;
+mod parse;
+pub mod png;
+pub mod signature;
+pub mod split;
+pub mod stream;
+
+pub use crate::borrowed::{ChunkRef, PngRef};
+pub use crate::chunk::Chunk;
+pub use crate::chunk_type::{ChunkProperties, ChunkType};
+pub use crate::compress::Compression;
+pub use crate::crc::Crc32;
+pub use crate::crypto::{Cipher, Identity, KdfParams, KeySource, PublicKey};
+pub use crate::envelope::{Envelope, Flags};
+pub use crate::error::{Error, Result};
+pub use crate::png::{CrcRepair, Placement, Png};
+pub use crate::signature::{ImageHasher, SignatureCheck, SigningKey, VerifyingKey};
+pub use crate::split::Piece;
+pub use crate::stream::{ChunkHeader, ChunkReader, ChunkWriter};
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Hides `message` in a new chunk of the given type, placed right before IEND
+pub fn embed(png: &mut Png, chunk_type: &str, message: &[u8]) -> Result<()> {
+    embed_at(png, chunk_type, message, Placement::default())
+}
+
+/// Hides `message` in a new chunk of the given type at the given placement.
+/// Fails with [`Error::CriticalChunkType`] for a critical type, see
+/// [`ChunkType::check_for_message`]; so do the other functions hiding a
+/// message.
+pub fn embed_at(
+    png: &mut Png,
+    chunk_type: &str,
+    message: &[u8],
+    placement: Placement,
+) -> Result<()> {
+    let chunk_type = message_type(chunk_type)?;
+    png.insert_chunk(Chunk::try_new(chunk_type, message.to_vec())?, placement);
+    Ok(())
+}
+
+/// Hides `message` split across chunks of the given type holding at most
+/// `piece_size` bytes of it each, see [`split`](crate::split)
+pub fn embed_split(
+    png: &mut Png,
+    chunk_type: &str,
+    message: &[u8],
+    piece_size: usize,
+    placement: Placement,
+) -> Result<()> {
+    let chunk_type = message_type(chunk_type)?;
+    png.insert_chunks(split_chunks(chunk_type, message, piece_size)?, placement);
+    Ok(())
+}
+
+/// Same as [`embed_at`], split like [`embed_split`] when `piece_size` is
+/// given, but takes any chunk type, critical ones included. That corrupts
+/// the image and is only meant for deliberate experiments.
+pub fn embed_forced(
+    png: &mut Png,
+    chunk_type: &str,
+    message: &[u8],
+    piece_size: Option<usize>,
+    placement: Placement,
+) -> Result<()> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    png.insert_chunks(secrets(chunk_type, message, piece_size)?, placement);
+    Ok(())
+}
+
+/// Parses the chunk type of a new message, refusing a critical one
+fn message_type(chunk_type: &str) -> Result<ChunkType> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    chunk_type.check_for_message()?;
+    Ok(chunk_type)
+}
+
+fn split_chunks(chunk_type: ChunkType, message: &[u8], piece_size: usize) -> Result<Vec<Chunk>> {
+    split::split(message, piece_size)?
+        .into_iter()
+        .map(|piece| Chunk::try_new(chunk_type, piece))
+        .collect()
+}
+
+/// The chunks holding `message`, split when `piece_size` is given
+fn secrets(chunk_type: ChunkType, message: &[u8], piece_size: Option<usize>) -> Result<Vec<Chunk>> {
+    match piece_size {
+        Some(piece_size) => split_chunks(chunk_type, message, piece_size),
+        None => Ok(vec![Chunk::try_new(chunk_type, message.to_vec())?]),
+    }
+}
+
+/// Copies a png from `reader` to `writer` one chunk at a time, hiding
+/// `message` in a new chunk at the given placement on the way. Chunk data
+/// is copied a block at a time, so memory use stays flat however large the
+/// image is. On an error `writer` holds a partial png and is best discarded.
+pub fn embed_stream<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+    message: &[u8],
+    placement: Placement,
+) -> Result<()> {
+    let chunk_type = message_type(chunk_type)?;
+    let secrets = secrets(chunk_type, message, None)?;
+    edit_stream(reader, writer, None, secrets, placement)?;
+    Ok(())
+}
+
+/// Same as [`embed_stream`] but split like [`embed_split`]
+pub fn embed_stream_split<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+    message: &[u8],
+    piece_size: usize,
+    placement: Placement,
+) -> Result<()> {
+    let chunk_type = message_type(chunk_type)?;
+    let secrets = secrets(chunk_type, message, Some(piece_size))?;
+    edit_stream(reader, writer, None, secrets, placement)?;
+    Ok(())
+}
+
+/// Same as [`embed_stream`] but leaves out every message already hidden
+/// under the chunk type, if there are any
+pub fn replace_stream<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+    message: &[u8],
+    placement: Placement,
+) -> Result<()> {
+    let wanted = message_type(chunk_type)?;
+    let secrets = secrets(wanted, message, None)?;
+    edit_stream(reader, writer, Some((wanted, None)), secrets, placement)?;
+    Ok(())
+}
+
+/// Same as [`replace_stream`] but split like [`embed_split`]
+pub fn replace_stream_split<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+    message: &[u8],
+    piece_size: usize,
+    placement: Placement,
+) -> Result<()> {
+    let wanted = message_type(chunk_type)?;
+    let secrets = secrets(wanted, message, Some(piece_size))?;
+    edit_stream(reader, writer, Some((wanted, None)), secrets, placement)?;
+    Ok(())
+}
+
+/// The streaming [`embed_forced`]: copies a png like [`embed_stream`] does
+/// under any chunk type, split when `piece_size` is given and leaving out
+/// the messages already under the type when `replace` is set
+pub fn embed_stream_forced<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+    message: &[u8],
+    piece_size: Option<usize>,
+    replace: bool,
+    placement: Placement,
+) -> Result<()> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let secrets = secrets(wanted, message, piece_size)?;
+    let remove = replace.then_some((wanted, None));
+    edit_stream(reader, writer, remove, secrets, placement)?;
+    Ok(())
+}
+
+/// Copies a png like [`embed_stream`] does, leaving out the message at
+/// `index` among the [`messages`] of the given type
+pub fn remove_message_stream<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+    index: usize,
+) -> Result<()> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let count = edit_stream(
+        reader,
+        writer,
+        Some((wanted, Some(index))),
+        Vec::new(),
+        Placement::default(),
+    )?;
+    match count {
+        0 => Err(Error::ChunkNotFound(wanted)),
+        count if index >= count => Err(Error::MessageNotFound {
+            chunk_type: wanted,
+            index,
+            count,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Copies a png like [`embed_stream`] does, leaving out every message of
+/// the given type
+pub fn remove_messages_stream<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+) -> Result<()> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let count = edit_stream(
+        reader,
+        writer,
+        Some((wanted, None)),
+        Vec::new(),
+        Placement::default(),
+    )?;
+    match count {
+        0 => Err(Error::ChunkNotFound(wanted)),
+        _ => Ok(()),
+    }
+}
+
+/// Copies chunks from `reader` to `writer`, leaving out the messages of a
+/// chunk type, only the one at the index when one is given, and adding
+/// `secrets` at `placement`. Only the chunks of that type are read whole,
+/// to tell their messages apart, the rest are copied a block at a time.
+/// Returns how many messages of the type there were.
+fn edit_stream<R: Read, W: Write>(
+    reader: &mut ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    remove: Option<(ChunkType, Option<usize>)>,
+    secrets: Vec<Chunk>,
+    placement: Placement,
+) -> Result<usize> {
+    let mut secret = Some(secrets);
+    let mut messages = split::Grouper::default();
+    while let Some(header) = reader.next_header()? {
+        let name = header.chunk_type.bytes();
+        let before = match placement {
+            Placement::BeforeIend => name == *b"IEND",
+            Placement::BeforeIdat => name == *b"IDAT",
+            Placement::AfterIhdr => false,
+        };
+        if before {
+            for secret in secret.take().into_iter().flatten() {
+                writer.write_chunk(&secret)?;
+            }
+        }
+        match remove {
+            Some((chunk_type, index)) if header.chunk_type == chunk_type => {
+                let chunk = reader.read_data()?;
+                let message = messages.message_of(chunk.data());
+                if index.is_some_and(|index| index != message) {
+                    writer.write_chunk(&chunk)?;
+                }
+            }
+            _ => reader.copy_data(writer)?,
+        }
+        if placement == Placement::AfterIhdr && name == *b"IHDR" {
+            for secret in secret.take().into_iter().flatten() {
+                writer.write_chunk(&secret)?;
+            }
+        }
+    }
+    // like Png::insert_chunk, fall back to the end without the anchor chunk
+    for secret in secret.into_iter().flatten() {
+        writer.write_chunk(&secret)?;
+    }
+    Ok(messages.count())
+}
+
+/// Returns the first message of the given type, put back together from
+/// its pieces when it was split
+pub fn extract<'a>(png: &'a Png, chunk_type: &str) -> Result<Cow<'a, [u8]>> {
+    let positions = message_positions(png, chunk_type, 0)?;
+    join_data(
+        positions
+            .iter()
+            .map(|&at| png.chunks()[at].data())
+            .collect(),
+    )
+}
+
+/// Same as [`extract`] on a borrowed [`PngRef`], a message held by a single
+/// chunk is not copied
+pub fn extract_ref<'a>(png: &PngRef<'a>, chunk_type: &str) -> Result<Cow<'a, [u8]>> {
+    join_message(&messages(png, chunk_type)?[0])
+}
+
+/// Every message hidden in chunks of the given type, in the order each
+/// first appears, as the chunks holding it: one chunk, or every piece of a
+/// split message. Fails with [`Error::ChunkNotFound`] when there is none.
+pub fn messages<'p, 'a>(
+    png: &'p PngRef<'a>,
+    chunk_type: &str,
+) -> Result<Vec<Vec<&'p ChunkRef<'a>>>> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let chunks = png.chunks_by_type(chunk_type);
+    if chunks.is_empty() {
+        return Err(Error::ChunkNotFound(wanted));
+    }
+    let messages = split::group(chunks.iter().map(|chunk| chunk.data()))
+        .into_iter()
+        .map(|positions| positions.into_iter().map(|at| chunks[at]).collect())
+        .collect();
+    Ok(messages)
+}
+
+/// Puts together a message found by [`messages`], borrowing it when it is
+/// held by a single chunk
+pub fn join_message<'a>(chunks: &[&ChunkRef<'a>]) -> Result<Cow<'a, [u8]>> {
+    join_data(chunks.iter().map(|chunk| chunk.data()).collect())
+}
+
+/// [`join_message`] on the data of the chunks
+fn join_data(data: Vec<&[u8]>) -> Result<Cow<'_, [u8]>> {
+    match *data.as_slice() {
+        [bytes] if !Piece::is_piece(bytes) => Ok(Cow::Borrowed(bytes)),
+        _ => split::join(data).map(Cow::Owned),
+    }
+}
+
+/// Positions in [`Png::chunks`] of the chunks holding the message at
+/// `index` among the [`messages`] of the given type
+fn message_positions(png: &Png, chunk_type: &str, index: usize) -> Result<Vec<usize>> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let positions: Vec<usize> = png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.chunk_type() == wanted)
+        .map(|(position, _)| position)
+        .collect();
+    let mut messages = split::group(positions.iter().map(|&at| png.chunks()[at].data()));
+    if messages.is_empty() {
+        return Err(Error::ChunkNotFound(wanted));
+    }
+    if index >= messages.len() {
+        return Err(Error::MessageNotFound {
+            chunk_type: wanted,
+            index,
+            count: messages.len(),
+        });
+    }
+    Ok(messages
+        .swap_remove(index)
+        .into_iter()
+        .map(|at| positions[at])
+        .collect())
+}
+
+/// Removes the message at `index` among the [`messages`] of the given type,
+/// every piece of it when it was split, and returns its chunks
+pub fn remove_message(png: &mut Png, chunk_type: &str, index: usize) -> Result<Vec<Chunk>> {
+    let positions = message_positions(png, chunk_type, index)?;
+    let mut position = 0;
+    Ok(png.remove_chunks(|_| {
+        position += 1;
+        positions.contains(&(position - 1))
+    }))
+}
+
+/// Replaces the message at `index` among the [`messages`] of the given type
+/// where it stands, rather than removing it and adding a new one. A split
+/// message is split again, into pieces as large as its largest one was.
+pub fn update_message(png: &mut Png, chunk_type: &str, index: usize, message: &[u8]) -> Result<()> {
+    let wanted = message_type(chunk_type)?;
+    let positions = message_positions(png, chunk_type, index)?;
+    let first = &png.chunks()[positions[0]];
+    let chunks = match Piece::try_from(first.data()) {
+        Ok(_) => {
+            // the last piece is usually short, the largest holds the piece size
+            let piece_size = positions
+                .iter()
+                .filter_map(|&at| Piece::try_from(png.chunks()[at].data()).ok())
+                .map(|piece| piece.data.len())
+                .max()
+                .unwrap_or(0);
+            split_chunks(wanted, message, piece_size.max(1))?
+        }
+        Err(_) => vec![Chunk::try_new(wanted, message.to_vec())?],
+    };
+    png.replace_chunks(&positions, chunks);
+    Ok(())
+}
+
+/// Removes every message of the given type and returns their chunks
+pub fn remove_messages(png: &mut Png, chunk_type: &str) -> Result<Vec<Chunk>> {
+    let wanted = ChunkType::from_str(chunk_type)?;
+    let removed = png.remove_chunks(|chunk| chunk.chunk_type() == wanted);
+    match removed.is_empty() {
+        true => Err(Error::ChunkNotFound(wanted)),
+        false => Ok(removed),
+    }
+}
+
+/// Removes the first chunk of the given type and returns it
+pub fn remove(png: &mut Png, chunk_type: &str) -> Result<Chunk> {
+    ChunkType::from_str(chunk_type)?;
+    png.remove_chunk(chunk_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        let chunk_type = ChunkType::from_str("FrSt").unwrap();
+        Png::from_chunks(vec![Chunk::new(
+            chunk_type,
+            b"I am the first chunk".to_vec(),
+        )])
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut png = testing_png();
+        embed(&mut png, "coOl", b"secret").unwrap();
+        assert_eq!(*extract(&png, "coOl").unwrap(), *b"secret");
+    }
+
+    #[test]
+    fn test_embed_before_iend() {
+        let mut png = testing_png();
+        let iend = ChunkType::from_str("IEND").unwrap();
+        png.append_chunk(Chunk::new(iend, Vec::new()));
+        embed(&mut png, "coOl", b"secret").unwrap();
+        let last = png.chunks().last().unwrap();
+        assert_eq!(last.chunk_type(), iend);
+    }
+
+    #[test]
+    fn test_embed_stream_matches_embed() {
+        let chunks = ["IHDR", "FrSt", "IDAT", "IEND"]
+            .iter()
+            .map(|name| Chunk::new(ChunkType::from_str(name).unwrap(), Vec::new()))
+            .collect();
+        let bytes = Png::from_chunks(chunks).as_bytes();
+
+        for placement in [
+            Placement::BeforeIend,
+            Placement::BeforeIdat,
+            Placement::AfterIhdr,
+        ] {
+            let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+            let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+            embed_stream(&mut reader, &mut writer, "coOl", b"secret", placement).unwrap();
+
+            let mut expected = Png::try_from(bytes.as_slice()).unwrap();
+            embed_at(&mut expected, "coOl", b"secret", placement).unwrap();
+            assert_eq!(writer.finish().unwrap(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_extract_ref() {
+        let mut png = testing_png();
+        let iend = ChunkType::from_str("IEND").unwrap();
+        png.append_chunk(Chunk::new(iend, Vec::new()));
+        embed(&mut png, "coOl", b"secret").unwrap();
+        let bytes = png.as_bytes();
+        let png = PngRef::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(*extract_ref(&png, "coOl").unwrap(), *b"secret");
+        assert!(extract_ref(&png, "miSs").is_err());
+    }
+
+    #[test]
+    fn test_extract_missing() {
+        let png = testing_png();
+        assert!(matches!(
+            extract(&png, "coOl"),
+            Err(Error::ChunkNotFound(chunk_type)) if chunk_type.to_string() == "coOl"
+        ));
+        assert!(matches!(
+            extract(&png, "co0l"),
+            Err(Error::InvalidChunkType)
+        ));
+    }
+
+    #[test]
+    fn test_split_round_trip() {
+        let message: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        for placement in [Placement::BeforeIend, Placement::AfterIhdr] {
+            let mut png = testing_png();
+            png.append_chunk(Chunk::new(ChunkType::from_str("IHDR").unwrap(), Vec::new()));
+            png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+            embed_split(&mut png, "coOl", &message, 300, placement).unwrap();
+            // pieces of another message under the same type are left alone
+            embed_split(&mut png, "coOl", b"other", 2, Placement::BeforeIend).unwrap();
+            assert_eq!(png.chunks().len(), 3 + 4 + 3);
+
+            let bytes = png.as_bytes();
+            let parsed = PngRef::try_from(bytes.as_slice()).unwrap();
+            assert_eq!(*extract_ref(&parsed, "coOl").unwrap(), *message);
+
+            assert_eq!(remove_message(&mut png, "coOl", 0).unwrap().len(), 4);
+            assert_eq!(png.chunks().len(), 3 + 3);
+        }
+    }
+
+    #[test]
+    fn test_embed_stream_split_matches_embed_split() {
+        let chunks = ["IHDR", "IDAT", "IEND"]
+            .iter()
+            .map(|name| Chunk::new(ChunkType::from_str(name).unwrap(), Vec::new()))
+            .collect();
+        let bytes = Png::from_chunks(chunks).as_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        embed_stream_split(
+            &mut reader,
+            &mut writer,
+            "coOl",
+            b"secret",
+            2,
+            Placement::BeforeIdat,
+        )
+        .unwrap();
+        let written = writer.finish().unwrap();
+        let png = PngRef::try_from(written.as_slice()).unwrap();
+        let names: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(names, ["IHDR", "coOl", "coOl", "coOl", "IDAT", "IEND"]);
+        assert_eq!(*extract_ref(&png, "coOl").unwrap(), *b"secret");
+    }
+
+    #[test]
+    fn test_extract_ref_of_a_single_chunk() {
+        let mut png = testing_png();
+        let iend = ChunkType::from_str("IEND").unwrap();
+        png.append_chunk(Chunk::new(iend, Vec::new()));
+        embed(&mut png, "coOl", b"secret").unwrap();
+        let bytes = png.as_bytes();
+        let png = PngRef::try_from(bytes.as_slice()).unwrap();
+        assert!(matches!(
+            extract_ref(&png, "coOl").unwrap(),
+            Cow::Borrowed(b"secret")
+        ));
+    }
+
+    #[test]
+    fn test_several_messages_of_one_type() {
+        let mut png = testing_png();
+        let iend = ChunkType::from_str("IEND").unwrap();
+        png.append_chunk(Chunk::new(iend, Vec::new()));
+        embed(&mut png, "coOl", b"first").unwrap();
+        embed_split(&mut png, "coOl", b"second", 2, Placement::BeforeIend).unwrap();
+        embed(&mut png, "coOl", b"third").unwrap();
+
+        let bytes = png.as_bytes();
+        let parsed = PngRef::try_from(bytes.as_slice()).unwrap();
+        let found = messages(&parsed, "coOl").unwrap();
+        let joined: Vec<Cow<[u8]>> = found
+            .iter()
+            .map(|chunks| join_message(chunks).unwrap())
+            .collect();
+        assert_eq!(joined, [&b"first"[..], b"second", b"third"]);
+        assert!(matches!(
+            messages(&parsed, "miSs"),
+            Err(Error::ChunkNotFound(_))
+        ));
+
+        assert_eq!(remove_message(&mut png, "coOl", 1).unwrap().len(), 3);
+        assert!(matches!(
+            remove_message(&mut png, "coOl", 2),
+            Err(Error::MessageNotFound {
+                index: 2,
+                count: 2,
+                ..
+            })
+        ));
+        assert_eq!(
+            remove_message(&mut png, "coOl", 1).unwrap()[0].data(),
+            b"third"
+        );
+        assert_eq!(remove_messages(&mut png, "coOl").unwrap().len(), 1);
+        assert!(matches!(
+            remove_messages(&mut png, "coOl"),
+            Err(Error::ChunkNotFound(_))
+        ));
+    }
+
+    fn stream<F>(bytes: &[u8], edit: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce(&mut ChunkReader<&[u8]>, &mut ChunkWriter<Vec<u8>>) -> Result<()>,
+    {
+        let mut reader = ChunkReader::new(bytes).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        edit(&mut reader, &mut writer).map(|_| writer.finish().unwrap())
+    }
+
+    #[test]
+    fn test_remove_and_replace_stream_match_in_memory() {
+        let chunks = ["IHDR", "IDAT", "IEND"]
+            .iter()
+            .map(|name| Chunk::new(ChunkType::from_str(name).unwrap(), vec![7; 100]))
+            .collect();
+        let mut png = Png::from_chunks(chunks);
+        embed(&mut png, "coOl", b"first").unwrap();
+        embed_split(&mut png, "coOl", b"second", 2, Placement::BeforeIdat).unwrap();
+        embed(&mut png, "coOl", b"third").unwrap();
+        let bytes = png.as_bytes();
+
+        let mut expected = Png::try_from(bytes.as_slice()).unwrap();
+        remove_message(&mut expected, "coOl", 1).unwrap();
+        let written = stream(&bytes, |r, w| remove_message_stream(r, w, "coOl", 1)).unwrap();
+        assert_eq!(written, expected.as_bytes());
+
+        let mut expected = Png::try_from(bytes.as_slice()).unwrap();
+        remove_messages(&mut expected, "coOl").unwrap();
+        let written = stream(&bytes, |r, w| remove_messages_stream(r, w, "coOl")).unwrap();
+        assert_eq!(written, expected.as_bytes());
+        embed(&mut expected, "coOl", b"new").unwrap();
+        let written = stream(&bytes, |r, w| {
+            replace_stream(r, w, "coOl", b"new", Placement::BeforeIend)
+        })
+        .unwrap();
+        assert_eq!(written, expected.as_bytes());
+
+        assert!(matches!(
+            stream(&bytes, |r, w| remove_message_stream(r, w, "coOl", 3)),
+            Err(Error::MessageNotFound {
+                index: 3,
+                count: 3,
+                ..
+            })
+        ));
+        assert!(matches!(
+            stream(&bytes, |r, w| remove_messages_stream(r, w, "miSs")),
+            Err(Error::ChunkNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_update_message_in_place() {
+        let mut png = testing_png();
+        embed(&mut png, "coOl", b"first").unwrap();
+        embed_split(&mut png, "coOl", b"second", 2, Placement::BeforeIend).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+
+        update_message(&mut png, "coOl", 0, b"changed").unwrap();
+        assert_eq!(png.chunks()[1].data(), b"changed");
+        update_message(&mut png, "coOl", 1, b"a longer second").unwrap();
+        // still split in two byte pieces, between the first message and IEND
+        assert_eq!(png.chunks().len(), 2 + 8 + 1);
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+
+        let bytes = png.as_bytes();
+        let parsed = PngRef::try_from(bytes.as_slice()).unwrap();
+        let found = messages(&parsed, "coOl").unwrap();
+        assert_eq!(*join_message(&found[1]).unwrap(), *b"a longer second");
+        assert!(matches!(
+            update_message(&mut png, "coOl", 2, b""),
+            Err(Error::MessageNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_update_message_keeps_the_piece_size() {
+        let coool = ChunkType::from_str("coOl").unwrap();
+        // the short last piece comes first
+        let mut chunks: Vec<Chunk> = split::split(b"second", 4)
+            .unwrap()
+            .into_iter()
+            .rev()
+            .map(|piece| Chunk::new(coool, piece))
+            .collect();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        let mut png = Png::from_chunks(chunks);
+
+        update_message(&mut png, "coOl", 0, b"a longer second").unwrap();
+        assert_eq!(png.chunks().len(), 4 + 1);
+        assert_eq!(*extract(&png, "coOl").unwrap(), *b"a longer second");
+    }
+
+    #[test]
+    fn test_extract_joins_a_split_message() {
+        let mut png = testing_png();
+        embed_split(&mut png, "coOl", b"secret", 2, Placement::BeforeIend).unwrap();
+        assert_eq!(*extract(&png, "coOl").unwrap(), *b"secret");
+    }
+
+    #[test]
+    fn test_critical_types_are_refused_unless_forced() {
+        let critical = |result: Result<()>| matches!(result, Err(Error::CriticalChunkType(_)));
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        let bytes = png.as_bytes();
+        for name in ["CoOl", "IDAT"] {
+            assert!(critical(embed(&mut png, name, b"secret")));
+            assert!(critical(embed_split(
+                &mut png,
+                name,
+                b"secret",
+                2,
+                Placement::default()
+            )));
+            assert!(critical(update_message(&mut png, name, 0, b"secret")));
+            assert!(critical(
+                stream(&bytes, |r, w| embed_stream(
+                    r,
+                    w,
+                    name,
+                    b"secret",
+                    Placement::default()
+                ))
+                .map(drop)
+            ));
+            assert!(critical(
+                stream(&bytes, |r, w| replace_stream_split(
+                    r,
+                    w,
+                    name,
+                    b"secret",
+                    2,
+                    Placement::default()
+                ))
+                .map(drop)
+            ));
+        }
+        assert_eq!(png.chunks().len(), 2);
+
+        embed_forced(&mut png, "CoOl", b"secret", None, Placement::default()).unwrap();
+        let forced = stream(&bytes, |r, w| {
+            embed_stream_forced(r, w, "CoOl", b"secret", None, false, Placement::default())
+        })
+        .unwrap();
+        assert_eq!(forced, png.as_bytes());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut png = testing_png();
+        embed(&mut png, "coOl", b"secret").unwrap();
+        let chunk = remove(&mut png, "coOl").unwrap();
+        assert_eq!(chunk.data(), b"secret");
+        assert!(extract(&png, "coOl").is_err());
+    }
+}
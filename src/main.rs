@@ -1,11 +1,12 @@
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
-use crate::args::PngMeArgs;
-use crate::commands::{decode, encode, print_chunks, remove};
+use crate::args::{Format, PngMeArgs};
+use crate::commands::{
+    decode, encode, info, keygen, print_chunks, print_json, remove, update, verify,
+};
 use clap::Parser;
+use secret_pics::{Error, Result};
+use std::process;
 
 #[derive(Parser)]
 #[command(name = "Secret Messages")]
@@ -15,17 +16,47 @@ use clap::Parser;
 struct Cli {
     #[command(subcommand)]
     pub args: PngMeArgs,
+    /// Output format for results and errors
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
 }
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
 
-fn main() -> Result<()> {
+fn main() {
     let cli = Cli::parse();
-    match cli.args {
+    if let Err(err) = run(cli.args, cli.format) {
+        report_error(&err, cli.format);
+        process::exit(1);
+    }
+}
+
+fn run(args: PngMeArgs, format: Format) -> Result<()> {
+    match args {
         PngMeArgs::Encode(args) => encode(args)?,
-        PngMeArgs::Decode(args) => decode(args)?,
+        PngMeArgs::Decode(args) => decode(args, format)?,
         PngMeArgs::Remove(args) => remove(args)?,
-        PngMeArgs::Print(args) => print_chunks(args)?,
+        PngMeArgs::Update(args) => update(args)?,
+        PngMeArgs::Print(args) => print_chunks(args, format)?,
+        PngMeArgs::Info(args) => info(args, format)?,
+        PngMeArgs::Keygen(args) => keygen(args, format)?,
+        PngMeArgs::Verify(args) => {
+            // the report is already printed, a failed check only sets the status
+            if !verify(args, format)? {
+                process::exit(1);
+            }
+        }
     }
     Ok(())
 }
+
+fn report_error(err: &Error, format: Format) {
+    #[derive(serde::Serialize)]
+    struct Report<'a> {
+        error: &'a Error,
+    }
+    // json errors go to stdout with everything else so a pipeline reading
+    // stdout always gets exactly one document
+    if format == Format::Json && print_json(&Report { error: err }).is_ok() {
+        return;
+    }
+    eprintln!("error: {}", err);
+}
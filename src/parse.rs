@@ -0,0 +1,134 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc::Crc32;
+use crate::png::{CrcRepair, Png};
+use crate::stream::ChunkHeader;
+use crate::{Error, Result};
+use std::convert::TryFrom;
+
+/// The checks shared by [`ChunkReader`](crate::ChunkReader), which reads
+/// a png from a stream, and [`PngRef`](crate::PngRef), which parses one in
+/// place, so both accept and refuse exactly the same files. Each hands it
+/// the bytes of one chunk at a time.
+#[derive(Debug)]
+pub(crate) struct Parser {
+    /// byte offset of the next chunk
+    pub(crate) offset: usize,
+    seen_iend: bool,
+    repair: bool,
+    pub(crate) repairs: Vec<CrcRepair>,
+}
+
+impl Parser {
+    /// Checks that the file starts with the png signature, `signature`
+    /// holding up to its first 8 bytes
+    pub(crate) fn new(signature: &[u8]) -> Result<Parser> {
+        if signature.len() < 8 {
+            return Err(Error::Truncated { offset: 0 });
+        }
+        if signature != Png::STANDARD_HEADER {
+            return Err(Error::BadSignature);
+        }
+        Ok(Parser {
+            offset: 8,
+            seen_iend: false,
+            repair: false,
+            repairs: Vec::new(),
+        })
+    }
+
+    /// Recomputes bad crcs instead of failing
+    pub(crate) fn repairing(mut self) -> Parser {
+        self.repair = true;
+        self
+    }
+
+    /// Reads the length and type of the next chunk from `head`, up to its
+    /// first 8 bytes. An empty `head` is the end of the file.
+    pub(crate) fn header(&self, head: &[u8]) -> Result<Option<ChunkHeader>> {
+        let offset = self.offset;
+        if head.is_empty() {
+            // chunks after IEND are still read so messages written by older
+            // versions, which appended them at the very end, are not lost
+            return match self.seen_iend {
+                true => Ok(None),
+                false => Err(Error::MissingIend { offset }),
+            };
+        }
+        if head.len() < 8 {
+            return Err(Error::Truncated { offset });
+        }
+        let length = u32::from_be_bytes(head[..4].try_into().unwrap());
+        if length > Chunk::MAX_LENGTH {
+            return Err(Error::LengthTooLarge { offset, length });
+        }
+        Ok(Some(ChunkHeader {
+            chunk_type: ChunkType(head[4..8].try_into().unwrap()),
+            length,
+            offset,
+        }))
+    }
+
+    /// Checks a chunk whose data and stored crc are at hand, returning its
+    /// type and computed crc
+    pub(crate) fn check_chunk(
+        &mut self,
+        header: ChunkHeader,
+        data: &[u8],
+        stored: u32,
+    ) -> Result<(ChunkType, u32)> {
+        let mut hasher = Crc32::new();
+        hasher.update(&header.chunk_type.bytes());
+        hasher.update(data);
+        let computed = hasher.finish();
+        let chunk_type = self.finish_chunk(header, stored, computed)?;
+        Ok((chunk_type, computed))
+    }
+
+    /// Checks the crc, or repairs it, and the chunk type once a chunk's data
+    /// has been read, and moves on to the next chunk
+    pub(crate) fn finish_chunk(
+        &mut self,
+        header: ChunkHeader,
+        stored: u32,
+        computed: u32,
+    ) -> Result<ChunkType> {
+        let ChunkHeader {
+            chunk_type, offset, ..
+        } = header;
+        if stored != computed {
+            if !self.repair {
+                return Err(Error::CrcMismatch {
+                    chunk_type,
+                    offset,
+                    expected: computed,
+                    found: stored,
+                });
+            }
+            self.repairs.push(CrcRepair {
+                chunk_type,
+                offset,
+                stored,
+                computed,
+            });
+        }
+        let chunk_type = ChunkType::try_from(chunk_type.bytes())?;
+        self.seen_iend |= chunk_type.bytes() == *b"IEND";
+        self.offset += 12 + header.length as usize;
+        Ok(chunk_type)
+    }
+}
+
+/// The error for a chunk that ends after `read` of its data and crc bytes
+pub(crate) fn short_chunk(header: ChunkHeader, read: usize) -> Error {
+    match read.checked_sub(4) {
+        None => Error::Truncated {
+            offset: header.offset,
+        },
+        Some(available) => Error::ChunkOverrun {
+            offset: header.offset,
+            length: header.length,
+            available,
+        },
+    }
+}
@@ -1,14 +1,40 @@
-use crate::chunk::calculate_from_bytes;
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
+use crate::stream::{ChunkReader, ChunkWriter};
 use crate::{Error, Result};
+use serde::ser::{Serialize, SerializeMap, Serializer};
 use std::convert::TryFrom;
 use std::fmt;
 use std::fs;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Write};
+use std::mem;
 use std::path::Path;
 use std::str::FromStr;
 
+/// A chunk whose stored crc was replaced while loading in repair mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcRepair {
+    pub chunk_type: ChunkType,
+    /// byte offset of the chunk in the original file
+    pub offset: usize,
+    /// the damaged crc that was in the file
+    pub stored: u32,
+    /// the crc computed from the chunk type and data
+    pub computed: u32,
+}
+
+/// Where [`Png::insert_chunk`] puts a new chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Placement {
+    /// right before IEND, the last spot strict decoders still read
+    #[default]
+    BeforeIend,
+    /// right before the first IDAT, among the other metadata chunks
+    BeforeIdat,
+    /// right after IHDR, at the very start of the image
+    AfterIhdr,
+}
+
 #[derive(Debug)]
 pub struct Png {
     header: [u8; 8],
@@ -27,29 +53,61 @@ impl Png {
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk)
     }
+
+    /// Inserts a chunk at the given placement, appending it at the end if the
+    /// chunk the placement is relative to does not exist
+    pub fn insert_chunk(&mut self, chunk: Chunk, placement: Placement) {
+        self.insert_chunks(vec![chunk], placement)
+    }
+
+    /// Inserts chunks together at the given placement, keeping their order
+    pub fn insert_chunks(&mut self, chunks: Vec<Chunk>, placement: Placement) {
+        let position = |name: &[u8; 4]| {
+            self.chunks
+                .iter()
+                .position(|chunk| chunk.chunk_type().bytes() == *name)
+        };
+        let index = match placement {
+            Placement::BeforeIend => position(b"IEND"),
+            Placement::BeforeIdat => position(b"IDAT"),
+            Placement::AfterIhdr => position(b"IHDR").map(|i| i + 1),
+        };
+        let index = index.unwrap_or(self.chunks.len());
+        self.chunks.splice(index..index, chunks);
+    }
+
+    /// Replaces the chunks at `positions`, indexes into [`Png::chunks`] in
+    /// ascending order, with `chunks` put where the first of them was
+    pub fn replace_chunks(&mut self, positions: &[usize], chunks: Vec<Chunk>) {
+        let Some(&first) = positions.first() else {
+            return;
+        };
+        for &position in positions.iter().rev() {
+            self.chunks.remove(position);
+        }
+        self.chunks.splice(first..first, chunks);
+    }
+
+    /// Removes every chunk `remove` picks, returning them in file order
+    pub fn remove_chunks<F: FnMut(&Chunk) -> bool>(&mut self, mut remove: F) -> Vec<Chunk> {
+        let (removed, kept) = mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|chunk| remove(chunk));
+        self.chunks = kept;
+        removed
+    }
     #[allow(dead_code)]
     pub fn header(&self) -> &[u8; 8] {
         &self.header
     }
 
-    pub fn chunks(&self) -> Vec<&Chunk> {
-        let mut chunks_vec = Vec::with_capacity(100);
-        for chunk in &self.chunks {
-            if !chunk.chunk_type.is_critical()
-                && !chunk.chunk_type.is_public()
-                && chunk.chunk_type.is_safe_to_copy()
-            {
-                chunks_vec.push(chunk)
-            }
-        }
-        chunks_vec
+    /// Every chunk in file order, the critical ones included
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
     }
     pub fn as_bytes(&self) -> Vec<u8> {
         let chunks = self.chunks.as_slice();
-        let chunk_bytes: Vec<u8> = chunks
-            .into_iter()
-            .flat_map(|byte| byte.as_bytes())
-            .collect();
+        let chunk_bytes: Vec<u8> = chunks.iter().flat_map(|byte| byte.as_bytes()).collect();
         let vec: Vec<u8> = self
             .header
             .iter()
@@ -60,43 +118,74 @@ impl Png {
     }
 
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        let chunk_bytes = ChunkType::from_str(chunk_type).unwrap();
-        for chunk in &self.chunks {
-            if chunk.chunk_type == chunk_bytes {
-                return Some(&chunk);
-            }
-        }
-        None
+        let chunk_bytes = ChunkType::from_str(chunk_type).ok()?;
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type() == chunk_bytes)
+    }
+
+    /// Every chunk of the given type, in file order
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&Chunk> {
+        let Ok(chunk_type) = ChunkType::from_str(chunk_type) else {
+            return Vec::new();
+        };
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type() == chunk_type)
+            .collect()
     }
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
-        let chunk = self.chunk_by_type(chunk_type);
-        match chunk {
-            Some(data) => {
-                let index = self
-                    .chunks
-                    .iter()
-                    .position(|c| c.chunk_type == data.chunk_type);
-                if let Some(i) = index {
-                    let chunk = self.chunks.remove(i);
-                    Ok(chunk)
-                } else {
-                    Err(Error::from("chunk is not available"))
-                }
-            }
-            None => Err(Error::from("chunk is not available")),
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type() == chunk_type);
+        match index {
+            Some(i) => Ok(self.chunks.remove(i)),
+            None => Err(Error::ChunkNotFound(chunk_type)),
         }
     }
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = fs::File::open(path)?;
-        let mut data = BufReader::new(file);
-        let mut buffer: Vec<u8> = Vec::with_capacity(10_000_000);
-        match data.read_to_end(&mut buffer) {
-            Ok(_) => {
-                let png = Png::try_from(buffer.as_slice().as_ref())?;
-                Ok(png)
-            }
-            Err(_) => Err(Error::from("failed to read")),
+        Png::from_reader(BufReader::new(file))
+    }
+
+    /// Reads a png from any reader, see [`ChunkReader`] to go chunk by chunk
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let (png, _) = Png::read(ChunkReader::new(reader)?)?;
+        Ok(png)
+    }
+
+    /// Loads a png whose chunks may have bad crcs, recomputing every crc that
+    /// does not match and reporting the chunks that were changed
+    pub fn from_bytes_repairing(bytes: &[u8]) -> Result<(Png, Vec<CrcRepair>)> {
+        Png::read(ChunkReader::new(bytes)?.repairing())
+    }
+
+    /// Same as [`Png::from_bytes_repairing`] but for a file on disk
+    pub fn from_file_repairing<P: AsRef<Path>>(path: P) -> Result<(Png, Vec<CrcRepair>)> {
+        let file = fs::File::open(path)?;
+        Png::from_reader_repairing(BufReader::new(file))
+    }
+
+    /// Same as [`Png::from_bytes_repairing`] but for any reader, such as stdin
+    pub fn from_reader_repairing<R: Read>(reader: R) -> Result<(Png, Vec<CrcRepair>)> {
+        Png::read(ChunkReader::new(reader)?.repairing())
+    }
+
+    /// Writes the png to `writer` chunk by chunk without building a copy first
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = ChunkWriter::new(writer)?;
+        for chunk in &self.chunks {
+            writer.write_chunk(chunk)?;
         }
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn read<R: Read>(mut reader: ChunkReader<R>) -> Result<(Png, Vec<CrcRepair>)> {
+        let chunks = reader.by_ref().collect::<Result<Vec<Chunk>>>()?;
+        Ok((Png::from_chunks(chunks), reader.repairs().to_vec()))
     }
 }
 impl fmt::Display for Png {
@@ -109,27 +198,19 @@ impl fmt::Display for Png {
     }
 }
 
+impl Serialize for Png {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("chunks", &self.chunks)?;
+        map.end()
+    }
+}
+
 impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Png> {
-        const HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
-        let png_header = &bytes[..8];
-        if HEADER == png_header {
-            let mut chunks_vec = Vec::<Chunk>::new();
-            let mut index = 8;
-            while index < bytes.len() {
-                let length = calculate_from_bytes(&bytes[index..index + 4]);
-                let chunk_type: [u8; 4] = bytes[index + 4..index + 8].try_into()?;
-                let data = bytes[index + 8..index + 8 + length as usize].to_vec();
-                let chunk_type = ChunkType::try_from(chunk_type)?;
-                chunks_vec.push(Chunk::new(chunk_type, data));
-                index += 12 + length as usize;
-            }
-            Ok(Png::from_chunks(chunks_vec))
-        } else {
-            Err(Error::from("invalid png"))
-        }
+        Png::from_reader(bytes)
     }
 }
 #[cfg(test)]
@@ -140,6 +221,7 @@ mod tests {
     use std::convert::TryFrom;
     use std::str::FromStr;
 
+    #[allow(clippy::vec_init_then_push)]
     fn testing_chunks() -> Vec<Chunk> {
         let mut chunks = Vec::new();
 
@@ -174,6 +256,7 @@ mod tests {
     fn test_valid_from_bytes() {
         let chunk_bytes: Vec<u8> = testing_chunks()
             .into_iter()
+            .chain(Some(chunk_from_strings("IEND", "").unwrap()))
             .flat_map(|chunk| chunk.as_bytes())
             .collect();
 
@@ -203,7 +286,7 @@ mod tests {
 
         let png = Png::try_from(bytes.as_ref());
 
-        assert!(png.is_err());
+        assert!(matches!(png, Err(Error::BadSignature)));
     }
 
     #[test]
@@ -243,6 +326,17 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
     }
 
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("FrSt", "Again").unwrap());
+        let chunks = png.chunks_by_type("FrSt");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(&chunks[1].data_as_string().unwrap(), "Again");
+        assert!(png.chunks_by_type("MiSs").is_empty());
+        assert!(png.chunks_by_type("bad").is_empty());
+    }
+
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
@@ -252,6 +346,84 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_chunk() {
+        let chunk_names = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().to_string())
+                .collect()
+        };
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(
+            chunk_from_strings("enDs", "").unwrap(),
+            Placement::BeforeIend,
+        );
+        png.insert_chunk(
+            chunk_from_strings("daTa", "").unwrap(),
+            Placement::BeforeIdat,
+        );
+        png.insert_chunk(
+            chunk_from_strings("stRt", "").unwrap(),
+            Placement::AfterIhdr,
+        );
+        assert_eq!(
+            chunk_names(&png),
+            vec!["IHDR", "stRt", "sRGB", "gAMA", "pHYs", "daTa", "IDAT", "RuSt", "enDs", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_insert_chunk_without_anchor() {
+        let mut png = testing_png();
+        png.insert_chunk(
+            chunk_from_strings("TeSt", "").unwrap(),
+            Placement::BeforeIend,
+        );
+        assert_eq!(png.chunks()[3].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_insert_chunks_keeps_order() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let chunks = vec![
+            chunk_from_strings("fiRs", "").unwrap(),
+            chunk_from_strings("seCo", "").unwrap(),
+        ];
+        png.insert_chunks(chunks, Placement::AfterIhdr);
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), "fiRs");
+        assert_eq!(png.chunks()[2].chunk_type().to_string(), "seCo");
+    }
+
+    #[test]
+    fn test_replace_chunks() {
+        let mut png = testing_png();
+        png.replace_chunks(
+            &[0, 2],
+            vec![
+                chunk_from_strings("NeWa", "").unwrap(),
+                chunk_from_strings("NeWb", "").unwrap(),
+            ],
+        );
+        let names: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(names, ["NeWa", "NeWb", "miDl"]);
+    }
+
+    #[test]
+    fn test_remove_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "one").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "two").unwrap());
+        let removed = png.remove_chunks(|chunk| chunk.chunk_type().to_string() == "TeSt");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[1].data(), b"two");
+        assert!(png.chunk_by_type("TeSt").is_none());
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -268,6 +440,107 @@ mod tests {
     }
 
     #[test]
+    fn test_short_file() {
+        let png = Png::try_from(&PNG_FILE[..5]);
+        assert!(matches!(png, Err(Error::Truncated { offset: 0 })));
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        // cut inside the length field of the first chunk
+        let png = Png::try_from(&PNG_FILE[..10]);
+        assert!(matches!(png, Err(Error::Truncated { offset: 8 })));
+    }
+
+    #[test]
+    fn test_chunk_overrun() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.truncate(100);
+        let png = Png::try_from(bytes.as_ref());
+        assert!(matches!(
+            png,
+            Err(Error::ChunkOverrun {
+                offset: 83,
+                length: 4681,
+                available: 5,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_length_too_large() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[8..12].copy_from_slice(&0x8000_0000_u32.to_be_bytes());
+        let png = Png::try_from(bytes.as_ref());
+        assert!(matches!(
+            png,
+            Err(Error::LengthTooLarge {
+                offset: 8,
+                length: 0x8000_0000,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_missing_iend() {
+        let bytes = &PNG_FILE[..PNG_FILE.len() - 12];
+        let png = Png::try_from(bytes);
+        assert!(matches!(
+            png,
+            Err(Error::MissingIend { offset }) if offset == PNG_FILE.len() - 12
+        ));
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = PNG_FILE.to_vec();
+        // last byte of the IHDR crc
+        bytes[32] ^= 0xff;
+        let png = Png::try_from(bytes.as_ref());
+        assert!(matches!(
+            png,
+            Err(Error::CrcMismatch { chunk_type, offset: 8, .. })
+                if chunk_type.bytes() == *b"IHDR"
+        ));
+    }
+
+    #[test]
+    fn test_crc_repair() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[32] ^= 0xff;
+        bytes[PNG_FILE.len() - 1] ^= 0xff;
+        let (png, repairs) = Png::from_bytes_repairing(&bytes).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+        let offsets: Vec<usize> = repairs.iter().map(|repair| repair.offset).collect();
+        assert_eq!(offsets, vec![8, PNG_FILE.len() - 12]);
+        assert_eq!(repairs[0].stored ^ repairs[0].computed, 0xff);
+    }
+
+    #[test]
+    fn test_crc_repair_from_reader() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[32] ^= 0xff;
+        assert!(Png::from_reader(bytes.as_slice()).is_err());
+        let (png, repairs) = Png::from_reader_repairing(bytes.as_slice()).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+        assert_eq!(repairs.len(), 1);
+    }
+
+    #[test]
+    fn test_repair_of_valid_file_changes_nothing() {
+        let (_, repairs) = Png::from_bytes_repairing(&PNG_FILE).unwrap();
+        assert!(repairs.is_empty());
+    }
+
+    #[test]
+    fn test_every_truncation_is_an_error() {
+        for end in 0..PNG_FILE.len() {
+            assert!(Png::try_from(&PNG_FILE[..end]).is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::iter_cloned_collect)]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
@@ -275,10 +548,47 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_round_trip_corpus() {
+        let secret = chunk_from_strings("coOl", "hidden").unwrap().as_bytes();
+        let empty = chunk_from_strings("emPt", "").unwrap().as_bytes();
+        let iend_at = PNG_FILE.len() - 12;
+        let corpus: Vec<Vec<u8>> = vec![
+            PNG_FILE.to_vec(),
+            TINY_PNG_FILE.to_vec(),
+            // a message appended after IEND by older versions
+            [&PNG_FILE[..], &secret].concat(),
+            [&PNG_FILE[..iend_at], &empty, &PNG_FILE[iend_at..]].concat(),
+        ];
+        for bytes in corpus {
+            let png = Png::try_from(bytes.as_ref()).unwrap();
+            assert_eq!(png.as_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_untouched_chunks_survive_edit() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let secret = chunk_from_strings("coOl", "hidden").unwrap();
+        png.insert_chunk(secret.clone(), Placement::BeforeIend);
+        let iend_at = PNG_FILE.len() - 12;
+        let expected = [
+            &PNG_FILE[..iend_at],
+            &secret.as_bytes(),
+            &PNG_FILE[iend_at..],
+        ]
+        .concat();
+        assert_eq!(png.as_bytes(), expected);
+
+        png.remove_chunk("coOl").unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
             .into_iter()
+            .chain(Some(chunk_from_strings("IEND", "").unwrap()))
             .flat_map(|chunk| chunk.as_bytes())
             .collect();
 
@@ -293,6 +603,29 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    #[test]
+    fn test_serialize_png() {
+        let png = Png::try_from(PNG_FILE.as_ref()).unwrap();
+        let json = serde_json::to_value(&png).unwrap();
+        let chunks = json["chunks"].as_array().unwrap();
+        assert_eq!(chunks.len(), png.chunks().len());
+        assert_eq!(chunks[0]["type"], "IHDR");
+        assert_eq!(chunks[4]["type"], "IDAT");
+        assert_eq!(chunks[4]["encoding"], "base64");
+        assert_eq!(chunks[5]["type"], "RuSt");
+        assert_eq!(chunks[5]["data"], "hey");
+    }
+
+    // A 1x1 grayscale image with a tEXt comment
+    #[rustfmt::skip]
+    const TINY_PNG_FILE: [u8; 91] = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 1, 0, 0, 0, 1, 8,
+        0, 0, 0, 0, 58, 126, 155, 85, 0, 0, 0, 12, 116, 69, 88, 116, 67, 111, 109, 109, 101, 110,
+        116, 0, 116, 105, 110, 121, 223, 166, 194, 44, 0, 0, 0, 10, 73, 68, 65, 84, 120, 156, 99,
+        104, 0, 0, 0, 130, 0, 129, 119, 205, 114, 182, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96,
+        130,
+    ];
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
     const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
@@ -0,0 +1,341 @@
+use crate::chunk_type::ChunkType;
+use crate::crypto::decode_key;
+use crate::envelope::{Envelope, Flags};
+use crate::{Error, Result};
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use ed25519_dalek::{Signer, SIGNATURE_LENGTH};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+const SCHEME_ED25519: u8 = 1;
+/// binding bit set when the signature also covers the image data
+const BIND_IMAGE: u8 = 1;
+const SIGNING_KEY_PREFIX: &str = "spsig:";
+const VERIFYING_KEY_PREFIX: &str = "spver:";
+/// keeps our signatures from being valid for anything else the key signs
+const DOMAIN: &[u8] = b"secret_pics signature v1\0";
+
+/// An ed25519 public key that checks signed messages, written as `spver:`
+/// and the base64url of its 32 bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            VERIFYING_KEY_PREFIX,
+            BASE64_URL_SAFE_NO_PAD.encode(self.0.as_bytes())
+        )
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = decode_key(s, VERIFYING_KEY_PREFIX)?;
+        VerifyingKey::try_from(bytes)
+    }
+}
+
+impl TryFrom<[u8; 32]> for VerifyingKey {
+    type Error = Error;
+
+    fn try_from(bytes: [u8; 32]) -> Result<Self> {
+        ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map(VerifyingKey)
+            .map_err(|_| Error::InvalidKey)
+    }
+}
+
+/// The ed25519 private key of a signer, written as `spsig:` and the
+/// base64url of its 32 byte seed. Like [`Identity`](crate::Identity) it is
+/// kept out of `Debug`, use [`SigningKey::to_secret_string`].
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    pub fn generate() -> Result<SigningKey> {
+        let mut seed = Zeroizing::new([0; 32]);
+        getrandom::getrandom(seed.as_mut()).map_err(io::Error::from)?;
+        Ok(SigningKey(ed25519_dalek::SigningKey::from_bytes(&seed)))
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+
+    pub fn to_secret_string(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{}{}",
+            SIGNING_KEY_PREFIX,
+            BASE64_URL_SAFE_NO_PAD.encode(self.0.as_bytes())
+        ))
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SigningKey")
+            .field(&self.verifying_key())
+            .finish()
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let seed = Zeroizing::new(decode_key(s, SIGNING_KEY_PREFIX)?);
+        Ok(SigningKey(ed25519_dalek::SigningKey::from_bytes(&seed)))
+    }
+}
+
+/// Hashes the IHDR and IDAT chunks of an image, in file order, so a
+/// signature can be bound to the picture as well as the message. Other
+/// chunks, our own included, are left out so adding or removing messages
+/// does not change the digest.
+#[derive(Debug, Clone, Default)]
+pub struct ImageHasher(Sha256);
+
+impl ImageHasher {
+    pub fn new() -> ImageHasher {
+        ImageHasher::default()
+    }
+
+    pub fn update(&mut self, chunk_type: ChunkType, data: &[u8]) {
+        if matches!(&chunk_type.bytes(), b"IHDR" | b"IDAT") {
+            self.0.update(chunk_type.bytes());
+            self.0.update((data.len() as u32).to_be_bytes());
+            self.0.update(data);
+        }
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+/// What [`verify`] found out about a signed envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureCheck {
+    pub signer: VerifyingKey,
+    /// whether the signature matches the message, and the image digest when
+    /// there is one
+    pub valid: bool,
+    /// the [`ImageHasher`] digest of the image the message was signed in,
+    /// compare it with the current image to see if the picture changed
+    pub image_digest: Option<[u8; 32]>,
+}
+
+fn signed_message(bindings: u8, image_digest: Option<&[u8]>, inner: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(DOMAIN.len() + 33 + inner.len());
+    message.extend_from_slice(DOMAIN);
+    message.push(bindings);
+    message.extend_from_slice(image_digest.unwrap_or_default());
+    message.extend_from_slice(inner);
+    message
+}
+
+/// Signs `envelope`, encrypted or not, and wraps it in a new envelope with
+/// only the signed flag set. With an `image_digest` from [`ImageHasher`]
+/// the signature also covers the image. The payload is laid out as:
+///
+/// | bytes | field |
+/// |-------|-------|
+/// | 1 | scheme, 1 for ed25519 |
+/// | 1 | bindings, bit 0 set when an image digest follows the key |
+/// | 32 | the signer's verifying key |
+/// | 32 | the image digest, only when bound |
+/// | 64 | signature |
+/// | rest | the signed inner envelope |
+pub fn sign(
+    envelope: &Envelope,
+    key: &SigningKey,
+    image_digest: Option<[u8; 32]>,
+) -> Result<Envelope> {
+    let inner = envelope.to_bytes()?;
+    let bindings = if image_digest.is_some() {
+        BIND_IMAGE
+    } else {
+        0
+    };
+    let digest = image_digest.as_ref().map(|digest| &digest[..]);
+    let signature = key.0.sign(&signed_message(bindings, digest, &inner));
+
+    let mut payload = vec![SCHEME_ED25519, bindings];
+    payload.extend_from_slice(key.0.verifying_key().as_bytes());
+    payload.extend_from_slice(digest.unwrap_or_default());
+    payload.extend_from_slice(&signature.to_bytes());
+    payload.extend_from_slice(&inner);
+    let mut flags = Flags::default();
+    flags.insert(Flags::SIGNED);
+    Ok(Envelope {
+        flags,
+        payload,
+        ..Envelope::default()
+    })
+}
+
+/// Checks the signature of an envelope made by [`sign`] and unwraps the
+/// inner envelope. A bad signature is reported in the [`SignatureCheck`]
+/// rather than as an error, so callers can still show who claims to have
+/// signed it.
+pub fn verify(envelope: &Envelope) -> Result<(SignatureCheck, Envelope)> {
+    if !envelope.flags.contains(Flags::SIGNED) {
+        return Err(Error::NotSigned);
+    }
+    let bytes = &envelope.payload;
+    let malformed = Error::MalformedEnvelope {
+        offset: bytes.len(),
+    };
+    if bytes.len() < 2 {
+        return Err(malformed);
+    }
+    if bytes[0] != SCHEME_ED25519 {
+        return Err(Error::UnsupportedSignatureScheme(bytes[0]));
+    }
+    let bindings = bytes[1];
+    if bindings & !BIND_IMAGE != 0 {
+        return Err(Error::MalformedEnvelope { offset: 1 });
+    }
+    let digest_len = if bindings & BIND_IMAGE != 0 { 32 } else { 0 };
+    let signature_at = 2 + 32 + digest_len;
+    let inner_at = signature_at + SIGNATURE_LENGTH;
+    if bytes.len() < inner_at {
+        return Err(malformed);
+    }
+    let signer = VerifyingKey::try_from(<[u8; 32]>::try_from(&bytes[2..34]).unwrap())?;
+    let digest = (digest_len > 0).then_some(&bytes[34..signature_at]);
+    let signature =
+        ed25519_dalek::Signature::from_bytes(&bytes[signature_at..inner_at].try_into().unwrap());
+    let inner = &bytes[inner_at..];
+    let valid = signer
+        .0
+        .verify_strict(&signed_message(bindings, digest, inner), &signature)
+        .is_ok();
+    let check = SignatureCheck {
+        signer,
+        valid,
+        image_digest: digest.map(|digest| digest.try_into().unwrap()),
+    };
+    Ok((check, Envelope::try_from(inner)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_envelope() -> Envelope {
+        Envelope {
+            content_type: Some("text/plain".to_string()),
+            ..Envelope::new(b"planted by me".to_vec())
+        }
+    }
+
+    fn testing_digest() -> [u8; 32] {
+        let mut hasher = ImageHasher::new();
+        hasher.update(ChunkType::from_str("IHDR").unwrap(), b"header");
+        hasher.update(ChunkType::from_str("IDAT").unwrap(), b"pixels");
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::generate().unwrap();
+        let signed = sign(&testing_envelope(), &key, None).unwrap();
+        assert!(signed.flags.contains(Flags::SIGNED));
+        let parsed = Envelope::try_from(signed.to_bytes().unwrap().as_slice()).unwrap();
+        let (check, inner) = verify(&parsed).unwrap();
+        assert!(check.valid);
+        assert_eq!(check.signer, key.verifying_key());
+        assert_eq!(check.image_digest, None);
+        assert_eq!(inner, testing_envelope());
+    }
+
+    #[test]
+    fn test_bound_image_digest() {
+        let key = SigningKey::generate().unwrap();
+        let signed = sign(&testing_envelope(), &key, Some(testing_digest())).unwrap();
+        let (check, _) = verify(&signed).unwrap();
+        assert!(check.valid);
+        assert_eq!(check.image_digest, Some(testing_digest()));
+
+        // swapping in the digest of another image breaks the signature
+        let mut swapped = signed;
+        swapped.payload[34] ^= 1;
+        assert!(!verify(&swapped).unwrap().0.valid);
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let key = SigningKey::generate().unwrap();
+        let signed = sign(&testing_envelope(), &key, None).unwrap();
+        let mut tampered = signed.clone();
+        let last = tampered.payload.len() - 1;
+        tampered.payload[last] ^= 1;
+        assert!(!verify(&tampered).unwrap().0.valid);
+
+        // a signature from someone else's key under the first signer's name
+        let other = SigningKey::generate().unwrap();
+        let mut forged = sign(&testing_envelope(), &other, None).unwrap();
+        forged.payload[2..34].copy_from_slice(key.verifying_key().0.as_bytes());
+        assert!(!verify(&forged).unwrap().0.valid);
+    }
+
+    #[test]
+    fn test_image_hasher_ignores_other_chunks() {
+        let mut hasher = ImageHasher::new();
+        hasher.update(ChunkType::from_str("IHDR").unwrap(), b"header");
+        hasher.update(ChunkType::from_str("ruSt").unwrap(), b"a message");
+        hasher.update(ChunkType::from_str("IDAT").unwrap(), b"pixels");
+        assert_eq!(hasher.finish(), testing_digest());
+
+        let mut hasher = ImageHasher::new();
+        hasher.update(ChunkType::from_str("IHDR").unwrap(), b"header");
+        hasher.update(ChunkType::from_str("IDAT").unwrap(), b"pixelz");
+        assert_ne!(hasher.finish(), testing_digest());
+    }
+
+    #[test]
+    fn test_unsigned_and_malformed() {
+        assert!(matches!(verify(&testing_envelope()), Err(Error::NotSigned)));
+        let key = SigningKey::generate().unwrap();
+        let signed = sign(&testing_envelope(), &key, None).unwrap();
+        let mut truncated = signed.clone();
+        truncated.payload.truncate(60);
+        assert!(matches!(
+            verify(&truncated),
+            Err(Error::MalformedEnvelope { .. })
+        ));
+        let mut unknown = signed;
+        unknown.payload[0] = 7;
+        assert!(matches!(
+            verify(&unknown),
+            Err(Error::UnsupportedSignatureScheme(7))
+        ));
+    }
+
+    #[test]
+    fn test_key_strings() {
+        let key = SigningKey::generate().unwrap();
+        let verifying = key.verifying_key();
+        assert!(verifying.to_string().starts_with("spver:"));
+        assert_eq!(
+            VerifyingKey::from_str(&verifying.to_string()).unwrap(),
+            verifying
+        );
+        let parsed = SigningKey::from_str(&key.to_secret_string()).unwrap();
+        assert_eq!(parsed.verifying_key(), verifying);
+        assert!(!format!("{:?}", key).contains(key.to_secret_string().as_str()));
+        assert!(matches!(
+            SigningKey::from_str(&verifying.to_string()),
+            Err(Error::InvalidKey)
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io;
+
+/// One piece of a message that was too large, or too conspicuous, for a
+/// single chunk. Each piece is stored as the data of its own chunk.
+///
+/// The layout, all integers big endian:
+///
+/// | bytes | field |
+/// |-------|-------|
+/// | 4 | magic, `SPPC` |
+/// | 1 | marker, `0xFF` |
+/// | 8 | message id, random and shared by every piece of the message |
+/// | 4 | index of this piece, from 0 |
+/// | 4 | number of pieces |
+/// | rest | the piece's bytes of the message |
+///
+/// No utf-8 text holds the marker byte, so a plain text message that
+/// happens to start with the magic is not taken for a piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Piece<'a> {
+    pub message_id: [u8; 8],
+    pub index: u32,
+    pub total: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> Piece<'a> {
+    pub const MAGIC: [u8; 4] = *b"SPPC";
+    pub const MARKER: u8 = 0xFF;
+    pub const HEADER_LEN: usize = 21;
+
+    /// Whether `bytes` start with the piece magic and marker
+    pub fn is_piece(bytes: &[u8]) -> bool {
+        bytes.starts_with(&Piece::MAGIC) && bytes.get(4) == Some(&Piece::MARKER)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Piece::HEADER_LEN + self.data.len());
+        bytes.extend_from_slice(&Piece::MAGIC);
+        bytes.push(Piece::MARKER);
+        bytes.extend_from_slice(&self.message_id);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.total.to_be_bytes());
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Piece<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        if !Piece::is_piece(bytes) {
+            return Err(Error::NotAPiece);
+        }
+        if bytes.len() < Piece::HEADER_LEN {
+            return Err(Error::MalformedPiece);
+        }
+        let read_u32 =
+            |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let piece = Piece {
+            message_id: bytes[5..13].try_into().unwrap(),
+            index: read_u32(13),
+            total: read_u32(17),
+            data: &bytes[Piece::HEADER_LEN..],
+        };
+        match piece.index < piece.total {
+            true => Ok(piece),
+            false => Err(Error::MalformedPiece),
+        }
+    }
+}
+
+/// Cuts `message` into pieces of at most `piece_size` bytes, each ready to
+/// be stored as chunk data, under a fresh random message id. An empty
+/// message still makes one piece.
+pub fn split(message: &[u8], piece_size: usize) -> Result<Vec<Vec<u8>>> {
+    if piece_size == 0 {
+        return Err(Error::InvalidPieceSize);
+    }
+    let mut message_id = [0; 8];
+    getrandom::getrandom(&mut message_id).map_err(io::Error::from)?;
+    let total = message.len().div_ceil(piece_size).max(1);
+    let total = u32::try_from(total).map_err(|_| Error::FieldTooLong {
+        field: "piece count",
+        length: total,
+    })?;
+    let pieces = (0..total)
+        .map(|index| {
+            let start = index as usize * piece_size;
+            let end = message.len().min(start + piece_size);
+            Piece {
+                message_id,
+                index,
+                total,
+                data: &message[start.min(end)..end],
+            }
+            .to_bytes()
+        })
+        .collect();
+    Ok(pieces)
+}
+
+/// Puts a message back together from the data of the chunks holding its
+/// pieces, in any order. The first piece decides the message, pieces of
+/// other messages are skipped. Fails if a piece is missing or appears
+/// twice.
+pub fn join<'a, I: IntoIterator<Item = &'a [u8]>>(chunks: I) -> Result<Vec<u8>> {
+    let mut message: Option<([u8; 8], u32)> = None;
+    let mut pieces = BTreeMap::new();
+    for bytes in chunks {
+        let piece = Piece::try_from(bytes)?;
+        let (message_id, total) = *message.get_or_insert((piece.message_id, piece.total));
+        if piece.message_id != message_id {
+            continue;
+        }
+        if piece.total != total {
+            return Err(Error::MalformedPiece);
+        }
+        if pieces.insert(piece.index, piece.data).is_some() {
+            return Err(Error::DuplicatePiece { index: piece.index });
+        }
+    }
+    let (_, total) = message.ok_or(Error::MalformedPiece)?;
+    if let Some(index) = (0..total).find(|index| !pieces.contains_key(index)) {
+        return Err(Error::MissingPiece { index, total });
+    }
+    // a BTreeMap iterates in index order
+    Ok(pieces.into_values().flatten().copied().collect())
+}
+
+/// Sorts the data of chunks sharing a type into messages, in the order
+/// each message first appears. A chunk that is not a [`Piece`] is a message
+/// on its own, pieces sharing a message id make up one message. Each
+/// message is given as the positions of its chunks in `chunks`.
+pub fn group<'a, I: IntoIterator<Item = &'a [u8]>>(chunks: I) -> Vec<Vec<usize>> {
+    let mut messages: Vec<Vec<usize>> = Vec::new();
+    let mut grouper = Grouper::default();
+    for (position, bytes) in chunks.into_iter().enumerate() {
+        match grouper.message_of(bytes) {
+            message if message == messages.len() => messages.push(vec![position]),
+            message => messages[message].push(position),
+        }
+    }
+    messages
+}
+
+/// Does what [`group`] does one chunk at a time, for chunks read from a
+/// stream
+#[derive(Debug, Default)]
+pub struct Grouper {
+    count: usize,
+    split: Vec<([u8; 8], usize)>,
+}
+
+impl Grouper {
+    /// The index of the message the chunk with this data belongs to
+    pub fn message_of(&mut self, bytes: &[u8]) -> usize {
+        let Ok(piece) = Piece::try_from(bytes) else {
+            self.count += 1;
+            return self.count - 1;
+        };
+        match self.split.iter().find(|(id, _)| *id == piece.message_id) {
+            Some(&(_, message)) => message,
+            None => {
+                self.split.push((piece.message_id, self.count));
+                self.count += 1;
+                self.count - 1
+            }
+        }
+    }
+
+    /// How many messages were seen so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Vec<u8> {
+        (0..=255).cycle().take(1000).collect()
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let message = message();
+        let pieces = split(&message, 300).unwrap();
+        assert_eq!(pieces.len(), 4);
+        assert_eq!(pieces[3].len(), Piece::HEADER_LEN + 100);
+        let joined = join(pieces.iter().map(Vec::as_slice)).unwrap();
+        assert_eq!(joined, message);
+    }
+
+    #[test]
+    fn test_join_in_any_order() {
+        let message = message();
+        let pieces = split(&message, 128).unwrap();
+        let joined = join(pieces.iter().rev().map(Vec::as_slice)).unwrap();
+        assert_eq!(joined, message);
+    }
+
+    #[test]
+    fn test_empty_message_is_one_piece() {
+        let pieces = split(b"", 10).unwrap();
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(join(pieces.iter().map(Vec::as_slice)).unwrap(), b"");
+        assert!(matches!(split(b"hello", 0), Err(Error::InvalidPieceSize)));
+    }
+
+    #[test]
+    fn test_missing_and_duplicate_pieces() {
+        let pieces = split(&message(), 300).unwrap();
+        assert!(matches!(
+            join([&pieces[0], &pieces[1], &pieces[3]].map(Vec::as_slice)),
+            Err(Error::MissingPiece { index: 2, total: 4 })
+        ));
+        assert!(matches!(
+            join([&pieces[0], &pieces[1], &pieces[1]].map(Vec::as_slice)),
+            Err(Error::DuplicatePiece { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_other_messages_are_skipped() {
+        let message = message();
+        let pieces = split(&message, 300).unwrap();
+        let others = split(b"another message", 4).unwrap();
+        let mixed = pieces.iter().zip(&others).flat_map(|(a, b)| [a, b]);
+        assert_eq!(join(mixed.map(Vec::as_slice)).unwrap(), message);
+    }
+
+    #[test]
+    fn test_group() {
+        let first = split(b"first message", 5).unwrap();
+        let second = split(b"second", 3).unwrap();
+        let chunks: Vec<&[u8]> = vec![
+            b"raw message",
+            &first[0],
+            &second[1],
+            &first[1],
+            &second[0],
+            &first[2],
+            b"another raw one",
+        ];
+        assert_eq!(
+            group(chunks),
+            vec![vec![0], vec![1, 3, 5], vec![2, 4], vec![6]]
+        );
+    }
+
+    #[test]
+    fn test_text_starting_with_the_magic_is_not_a_piece() {
+        let text = b"SPPC is how this message happens to begin";
+        assert!(matches!(
+            Piece::try_from(text.as_ref()),
+            Err(Error::NotAPiece)
+        ));
+        let chunks: Vec<&[u8]> = vec![text, text];
+        assert_eq!(group(chunks), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn test_malformed_pieces() {
+        assert!(matches!(
+            Piece::try_from(b"SPMS not a piece".as_ref()),
+            Err(Error::NotAPiece)
+        ));
+        assert!(matches!(
+            Piece::try_from(b"SPPC\xFFshort".as_ref()),
+            Err(Error::MalformedPiece)
+        ));
+        let mut piece = split(b"hello", 10).unwrap().remove(0);
+        piece[16] = 1;
+        assert!(matches!(
+            Piece::try_from(piece.as_slice()),
+            Err(Error::MalformedPiece)
+        ));
+    }
+}
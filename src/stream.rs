@@ -0,0 +1,413 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc::Crc32;
+use crate::parse::{short_chunk, Parser};
+use crate::png::{CrcRepair, Png};
+use crate::{Error, Result};
+use std::io::{self, Read, Write};
+
+/// The length and type of a chunk, read ahead of its data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkHeader {
+    /// as stored, it is only checked once the data and crc have been read
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    /// byte offset of the chunk in the stream
+    pub offset: usize,
+}
+
+/// Reads a png one chunk at a time, so only the current chunk is ever held
+/// in memory. The signature is checked on creation and every chunk's crc
+/// is verified as it is read.
+///
+/// Iterating gives whole chunks. To avoid holding even one chunk, read its
+/// header with [`ChunkReader::next_header`] and then copy its data with
+/// [`ChunkReader::copy_data`].
+pub struct ChunkReader<R: Read> {
+    reader: R,
+    parser: Parser,
+    pending: Option<ChunkHeader>,
+    finished: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut signature = Vec::with_capacity(8);
+        (&mut reader).take(8).read_to_end(&mut signature)?;
+        Ok(ChunkReader {
+            reader,
+            parser: Parser::new(&signature)?,
+            pending: None,
+            finished: false,
+        })
+    }
+
+    /// Recomputes bad crcs instead of failing, see [`ChunkReader::repairs`]
+    pub fn repairing(mut self) -> Self {
+        self.parser = self.parser.repairing();
+        self
+    }
+
+    /// The chunks whose crc was replaced so far in repair mode
+    pub fn repairs(&self) -> &[CrcRepair] {
+        &self.parser.repairs
+    }
+
+    /// Byte offset of the next chunk
+    pub fn offset(&self) -> usize {
+        self.parser.offset
+    }
+
+    /// Reads the length and type of the next chunk, `None` after the last
+    /// one. Its data is then read with [`ChunkReader::read_data`] or copied
+    /// with [`ChunkReader::copy_data`]; data that is neither is skipped,
+    /// crc check and all, when the next header is read.
+    pub fn next_header(&mut self) -> Result<Option<ChunkHeader>> {
+        if let Some(header) = self.pending.take() {
+            self.skip_data(header)?;
+        }
+        let mut head = Vec::with_capacity(8);
+        (&mut self.reader).take(8).read_to_end(&mut head)?;
+        let header = self.parser.header(&head)?;
+        self.pending = header;
+        Ok(header)
+    }
+
+    /// Reads the data of the chunk whose header was just read
+    ///
+    /// # Panics
+    ///
+    /// If there is no such header
+    pub fn read_data(&mut self) -> Result<Chunk> {
+        let header = self.pending.take().expect("no chunk header was read");
+        // data and crc land in a buffer that only grows as bytes actually
+        // arrive, so a lying length cannot make us allocate 2 GiB
+        let mut buffer = Vec::new();
+        (&mut self.reader)
+            .take(header.length as u64 + 4)
+            .read_to_end(&mut buffer)?;
+        let data_end = header.length as usize;
+        if buffer.len() < data_end + 4 {
+            return Err(short_chunk(header, buffer.len()));
+        }
+        let stored = u32::from_be_bytes(buffer[data_end..].try_into().unwrap());
+        let (chunk_type, computed) =
+            self.parser
+                .check_chunk(header, &buffer[..data_end], stored)?;
+        buffer.truncate(data_end);
+        // the chunk is kept exactly as read instead of going through
+        // Chunk::new, so untouched chunks are written back byte for byte;
+        // `computed` only differs from the stored crc for repaired chunks
+        Ok(Chunk::from_parts_unchecked(
+            header.length,
+            chunk_type,
+            buffer,
+            computed,
+        ))
+    }
+
+    /// Copies the chunk whose header was just read to `writer` through
+    /// [`ChunkWriter::copy_chunk`], a block at a time, so even a huge IDAT
+    /// is never held in memory. The crc is checked as the data goes by, so
+    /// on an error `writer` holds part of a bad chunk and is best discarded.
+    ///
+    /// # Panics
+    ///
+    /// If there is no such header
+    pub fn copy_data<W: Write>(&mut self, writer: &mut ChunkWriter<W>) -> Result<()> {
+        let header = self.pending.take().expect("no chunk header was read");
+        let mut data = CrcReader::new(&mut self.reader, header);
+        match writer.copy_chunk(header.chunk_type, header.length, &mut data) {
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(short_chunk(header, data.read));
+            }
+            result => result?,
+        }
+        let computed = data.hasher.finish();
+        let stored = self.read_crc(header)?;
+        self.parser.finish_chunk(header, stored, computed)?;
+        Ok(())
+    }
+
+    fn skip_data(&mut self, header: ChunkHeader) -> Result<()> {
+        let mut data = CrcReader::new(&mut self.reader, header);
+        let skipped = io::copy(&mut data, &mut io::sink())?;
+        if skipped < header.length as u64 {
+            return Err(short_chunk(header, skipped as usize));
+        }
+        let computed = data.hasher.finish();
+        let stored = self.read_crc(header)?;
+        self.parser.finish_chunk(header, stored, computed)?;
+        Ok(())
+    }
+
+    fn read_crc(&mut self, header: ChunkHeader) -> Result<u32> {
+        let mut crc = Vec::with_capacity(4);
+        (&mut self.reader).take(4).read_to_end(&mut crc)?;
+        match crc.try_into() {
+            Ok(crc) => Ok(u32::from_be_bytes(crc)),
+            Err(crc) => Err(short_chunk(header, header.length as usize + crc.len())),
+        }
+    }
+}
+
+/// Hashes a chunk's data for its crc while it is read
+struct CrcReader<R: Read> {
+    reader: io::Take<R>,
+    hasher: Crc32,
+    read: usize,
+}
+
+impl<R: Read> CrcReader<R> {
+    fn new(reader: R, header: ChunkHeader) -> Self {
+        let mut hasher = Crc32::new();
+        hasher.update(&header.chunk_type.bytes());
+        CrcReader {
+            reader: reader.take(header.length as u64),
+            hasher,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.read += read;
+        Ok(read)
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let chunk = match self.next_header() {
+            Ok(Some(_)) => self.read_data().map(Some),
+            other => other.map(|_| None),
+        };
+        match chunk {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Writes a png one chunk at a time, starting with the signature
+pub struct ChunkWriter<W: Write> {
+    writer: W,
+    offset: usize,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(ChunkWriter { writer, offset: 8 })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        self.writer.write_all(&chunk.length().to_be_bytes())?;
+        self.writer.write_all(&chunk.chunk_type().bytes())?;
+        self.writer.write_all(chunk.data())?;
+        self.writer.write_all(&chunk.crc().to_be_bytes())?;
+        self.offset += 12 + chunk.data().len();
+        Ok(())
+    }
+
+    /// Writes a chunk whose `length` bytes of data come from `data`, hashing
+    /// them on the way so a huge chunk never has to be held in memory
+    pub fn copy_chunk<R: Read>(
+        &mut self,
+        chunk_type: ChunkType,
+        length: u32,
+        mut data: R,
+    ) -> Result<()> {
+        if length > Chunk::MAX_LENGTH {
+            return Err(Error::LengthTooLarge {
+                offset: self.offset,
+                length,
+            });
+        }
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&chunk_type.bytes())?;
+        let mut hasher = Crc32::new();
+        hasher.update(&chunk_type.bytes());
+        let mut buffer = [0_u8; 8192];
+        let mut remaining = length as usize;
+        while remaining > 0 {
+            let wanted = remaining.min(buffer.len());
+            data.read_exact(&mut buffer[..wanted])?;
+            hasher.update(&buffer[..wanted]);
+            self.writer.write_all(&buffer[..wanted])?;
+            remaining -= wanted;
+        }
+        self.writer.write_all(&hasher.finish().to_be_bytes())?;
+        self.offset += 12 + length as usize;
+        Ok(())
+    }
+
+    /// Flushes everything written and hands back the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for (chunk_type, data) in [("IHDR", "header"), ("ruSt", "message"), ("IEND", "")] {
+            let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+            writer
+                .write_chunk(&Chunk::new(chunk_type, data.as_bytes().to_vec()))
+                .unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let bytes = testing_bytes();
+        let chunks: Vec<Chunk> = ChunkReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].data_as_string().unwrap(), "message");
+    }
+
+    #[test]
+    fn test_reader_offsets() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.offset(), 8);
+        reader.next().unwrap().unwrap();
+        assert_eq!(reader.offset(), 8 + 12 + 6);
+    }
+
+    #[test]
+    fn test_reader_stops_after_error() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(&bytes[..30]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(Error::Truncated { offset: 26 }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_copy_chunk_matches_write_chunk() {
+        let chunk_type = ChunkType::from_str("IDAT").unwrap();
+        let data: Vec<u8> = (0..20_000_u32).map(|i| (i % 251) as u8).collect();
+
+        let mut copied = ChunkWriter::new(Vec::new()).unwrap();
+        copied
+            .copy_chunk(chunk_type, data.len() as u32, data.as_slice())
+            .unwrap();
+        let mut written = ChunkWriter::new(Vec::new()).unwrap();
+        written.write_chunk(&Chunk::new(chunk_type, data)).unwrap();
+        assert_eq!(copied.finish().unwrap(), written.finish().unwrap());
+    }
+
+    #[test]
+    fn test_copy_chunk_short_data() {
+        let chunk_type = ChunkType::from_str("IDAT").unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        assert!(matches!(
+            writer.copy_chunk(chunk_type, 10, [0_u8; 5].as_ref()),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_copy_data() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        while let Some(header) = reader.next_header().unwrap() {
+            assert_eq!(header.offset, writer.offset);
+            reader.copy_data(&mut writer).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_copy_data_checks_crc() {
+        let mut bytes = testing_bytes();
+        bytes[42] ^= 0xff;
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        reader.next_header().unwrap();
+        reader.copy_data(&mut writer).unwrap();
+        reader.next_header().unwrap();
+        assert!(matches!(
+            reader.copy_data(&mut writer),
+            Err(Error::CrcMismatch { offset: 26, .. })
+        ));
+
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap().repairing();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        while reader.next_header().unwrap().is_some() {
+            reader.copy_data(&mut writer).unwrap();
+        }
+        assert_eq!(reader.repairs().len(), 1);
+        assert_eq!(writer.finish().unwrap(), testing_bytes());
+    }
+
+    #[test]
+    fn test_unread_data_is_skipped_and_checked() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let types: Vec<ChunkType> = std::iter::from_fn(|| reader.next_header().unwrap())
+            .map(|header| header.chunk_type)
+            .collect();
+        assert_eq!(types.len(), 3);
+
+        let mut reader = ChunkReader::new(&bytes[..40]).unwrap();
+        reader.next_header().unwrap();
+        reader.next_header().unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        assert!(matches!(
+            reader.copy_data(&mut writer),
+            Err(Error::ChunkOverrun {
+                offset: 26,
+                length: 7,
+                available: 2
+            })
+        ));
+        let mut reader = ChunkReader::new(&bytes[..40]).unwrap();
+        reader.next_header().unwrap();
+        reader.next_header().unwrap();
+        assert!(matches!(
+            reader.read_data(),
+            Err(Error::ChunkOverrun { available: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_then_read_round_trip() {
+        let bytes = testing_bytes();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for chunk in ChunkReader::new(bytes.as_slice()).unwrap() {
+            writer.write_chunk(&chunk.unwrap()).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), bytes);
+    }
+}